@@ -0,0 +1,214 @@
+use std::{fs::read_dir, path::Path, sync::OnceLock};
+
+use chrono::{DateTime, FixedOffset};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    ffmpeg::VideoTimestamp,
+    interval::{IntervalSet, TimeRange},
+};
+
+pub const PLAYLIST_DIRS: [&str; 2] = ["urls/aftershow/", "urls/uploads/"];
+
+#[derive(Debug, Deserialize)]
+pub struct PlaylistItemResponse {
+    pub items: Vec<PlaylistItem>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PlaylistItem {
+    pub snippet: PlaylistItemSnippet,
+    #[serde(rename = "contentDetails")]
+    pub content_details: PlaylistItemContentDetails,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PlaylistItemSnippet {
+    pub title: String,
+    #[serde(default)]
+    pub description: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PlaylistItemContentDetails {
+    #[serde(rename = "videoId")]
+    pub video_id: String,
+    #[serde(rename = "videoPublishedAt")]
+    pub video_published_at: DateTime<FixedOffset>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Chapter {
+    pub start: VideoTimestamp,
+    pub label: String,
+}
+
+impl Chapter {
+    pub fn is_mictest(&self) -> bool {
+        static REGEX: OnceLock<Regex> = OnceLock::new();
+        REGEX
+            .get_or_init(|| Regex::new(r"(?i)\bmic(rophone)?[\s-]*test").unwrap())
+            .is_match(&self.label)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CatalogEntry {
+    pub video_id: String,
+    pub title: String,
+    pub published_at: DateTime<FixedOffset>,
+    pub chapters: Vec<Chapter>,
+}
+
+impl CatalogEntry {
+    pub fn from_playlist_item(item: PlaylistItem) -> Self {
+        Self {
+            chapters: parse_chapters(&item.snippet.description),
+            video_id: item.content_details.video_id,
+            title: item.snippet.title,
+            published_at: item.content_details.video_published_at,
+        }
+    }
+
+    /// Ranges covered by chapters labelled as a mic test. A chapter ends where the next one
+    /// starts; the last chapter ends at `duration` if known and is skipped otherwise.
+    pub fn mictest_chapter_ranges(&self, duration: Option<VideoTimestamp>) -> IntervalSet {
+        let mut ranges = IntervalSet::new();
+        for (i, chapter) in self.chapters.iter().enumerate() {
+            if !chapter.is_mictest() {
+                continue;
+            }
+            let end = match self.chapters.get(i + 1) {
+                Some(next) => next.start,
+                None => match duration {
+                    Some(ref duration) => *duration,
+                    None => continue,
+                },
+            };
+            if let Ok(range) = TimeRange::new(chapter.start, end) {
+                ranges.insert(range);
+            }
+        }
+        ranges
+    }
+}
+
+pub fn load_playlist_items<P: AsRef<Path>>(dir: P) -> anyhow::Result<Vec<PlaylistItem>> {
+    let mut items = Vec::new();
+    for entry in read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().is_none_or(|ext| ext != "json") {
+            continue;
+        }
+        let response =
+            serde_json::from_reader::<_, PlaylistItemResponse>(std::fs::File::open(&path)?)?;
+        items.extend(response.items);
+    }
+    Ok(items)
+}
+
+pub fn load_catalog() -> anyhow::Result<Vec<CatalogEntry>> {
+    let mut entries = Vec::new();
+    for dir in PLAYLIST_DIRS {
+        entries.extend(
+            load_playlist_items(dir)?
+                .into_iter()
+                .map(CatalogEntry::from_playlist_item),
+        );
+    }
+    Ok(entries)
+}
+
+/// Extracts `mm:ss` / `hh:mm:ss` chapter markers from a video description.
+///
+/// Every line holding exactly one timestamp is treated as a chapter, the rest of the line
+/// (minus separators such as `-`, `|` or brackets) being its label.
+pub fn parse_chapters(description: &str) -> Vec<Chapter> {
+    static REGEX: OnceLock<Regex> = OnceLock::new();
+    let regex = REGEX.get_or_init(|| Regex::new(r"\b(?:\d{1,2}:)?\d{1,3}:\d{2}\b").unwrap());
+    let separators: &[char] = &['-', '–', '—', ':', '|', '(', ')', '[', ']', '.', ' ', '\t'];
+
+    let mut chapters = description
+        .lines()
+        .filter_map(|line| {
+            let mut matches = regex.find_iter(line);
+            let whole = matches.next()?;
+            if matches.next().is_some() {
+                return None;
+            }
+            let start = whole.as_str().parse::<VideoTimestamp>().ok()?;
+            let label = format!(
+                "{} {}",
+                line[..whole.start()].trim_matches(separators),
+                line[whole.end()..].trim_matches(separators)
+            );
+            let label = label.trim();
+            if label.is_empty() {
+                return None;
+            }
+            Some(Chapter {
+                start,
+                label: label.to_string(),
+            })
+        })
+        .collect::<Vec<_>>();
+    chapters.sort_by_key(|c| c.start);
+    chapters
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_chapters() {
+        let description = "Thanks for watching!\n\
+            0:00 Intro\n\
+            1:23 - Mic Test\n\
+            Unboxing (12:05)\n\
+            1:02:03 | outro\n\
+            Links: https://example.com/watch?t=1:00 and 2:00\n";
+        let chapters = parse_chapters(description);
+        assert_eq!(
+            chapters
+                .iter()
+                .map(|c| (c.start.as_float_seconds() as u64, c.label.as_str()))
+                .collect::<Vec<_>>(),
+            vec![
+                (0, "Intro"),
+                (83, "Mic Test"),
+                (725, "Unboxing"),
+                (3723, "outro"),
+            ]
+        );
+        assert!(chapters[1].is_mictest());
+        assert!(!chapters[0].is_mictest());
+    }
+
+    #[test]
+    fn test_mictest_chapter_ranges() {
+        let entry = CatalogEntry {
+            video_id: "test".into(),
+            title: "test".into(),
+            published_at: DateTime::parse_from_rfc3339("2023-01-01T00:00:00Z").unwrap(),
+            chapters: parse_chapters(
+                "0:00 intro\n1:00 mic test\n2:30 review\n9:00 microphone test",
+            ),
+        };
+        let ranges = entry
+            .mictest_chapter_ranges(None)
+            .into_iter()
+            .collect::<Vec<_>>();
+        assert_eq!(ranges.len(), 1);
+        assert_eq!(ranges[0].start().as_float_seconds(), 60.0);
+        assert_eq!(ranges[0].end().as_float_seconds(), 150.0);
+
+        let ranges = entry
+            .mictest_chapter_ranges(Some(VideoTimestamp::from_float_seconds(600.0)))
+            .into_iter()
+            .collect::<Vec<_>>();
+        assert_eq!(ranges.len(), 2);
+        assert_eq!(ranges[1].end().as_float_seconds(), 600.0);
+    }
+}
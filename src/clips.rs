@@ -0,0 +1,346 @@
+use std::{
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    path::Path,
+};
+
+use chrono::{DateTime, Utc};
+use serde::{de, Deserialize, Deserializer, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    ffmpeg::{probe::StreamInfo, process::PartialOutput, VideoTimestamp},
+    interval::{IntervalSet, TimeRange},
+    refine::BoundaryFlag,
+};
+
+pub const CLIPS_SCHEMA_VERSION: u32 = 2;
+
+/// Detected mic tests of one video, as stored in `data/clips/{id}.json`.
+///
+/// Files without `provenance` were either migrated from the original `{"ranges": [...]}` format
+/// or never analysed, so an empty `ranges` only means "no mic test" when `provenance` is set.
+#[derive(Debug, Clone, Serialize)]
+pub struct ClipsInfo {
+    pub version: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provenance: Option<Provenance>,
+    pub ranges: Vec<DetectedRange>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DetectedRange {
+    pub range: TimeRange,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub confidence: Option<f64>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub flags: Vec<BoundaryFlag>,
+}
+
+impl DetectedRange {
+    pub fn new(range: TimeRange) -> Self {
+        Self {
+            range,
+            confidence: None,
+            flags: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Provenance {
+    pub detector: String,
+    pub detector_version: String,
+    pub parameters: serde_json::Value,
+    pub source: SourceInfo,
+    pub analyzed_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SourceInfo {
+    pub duration: VideoTimestamp,
+    pub width: Option<u64>,
+    pub height: Option<u64>,
+    pub frame_rate: Option<(u64, u64)>,
+    /// See [`quick_file_hash`].
+    pub hash: Option<String>,
+}
+
+impl SourceInfo {
+    pub fn from_probe(path: &str, probe: &StreamInfo) -> anyhow::Result<Self> {
+        let video = probe.video_stream();
+        Ok(Self {
+            duration: probe.duration()?,
+            width: video.and_then(|s| s.width),
+            height: video.and_then(|s| s.height),
+            frame_rate: probe.video_frame_rate(),
+            hash: Some(quick_file_hash(path)?),
+        })
+    }
+}
+
+impl ClipsInfo {
+    pub fn new(ranges: Vec<DetectedRange>, provenance: Option<Provenance>) -> Self {
+        let mut ranges = ranges;
+        ranges.sort_by(|a, b| a.range.cmp(&b.range));
+        Self {
+            version: CLIPS_SCHEMA_VERSION,
+            provenance,
+            ranges,
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        Ok(serde_json::from_reader(File::open(path)?)?)
+    }
+
+    /// Writes under a temporary name first, so an interrupted save never leaves a truncated
+    /// file behind.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> anyhow::Result<()> {
+        let partial = PartialOutput::new(path)?;
+        serde_json::to_writer(File::create(partial.path())?, self)?;
+        partial.commit()?;
+        Ok(())
+    }
+
+    pub fn is_analysed(&self) -> bool {
+        self.provenance.is_some()
+    }
+
+    pub fn intervals(&self) -> IntervalSet {
+        self.ranges.iter().map(|r| r.range.clone()).collect()
+    }
+
+    /// Checks what the type system doesn't: ranges are sorted, disjoint, within the source video
+    /// when its duration is known, and confidences are probabilities.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.version > CLIPS_SCHEMA_VERSION {
+            return Err(anyhow::anyhow!(
+                "Unsupported clips schema version {}",
+                self.version
+            ));
+        }
+        for (a, b) in self.ranges.iter().zip(self.ranges.iter().skip(1)) {
+            if b.range.start() < a.range.end() {
+                return Err(anyhow::anyhow!(
+                    "Range {} overlaps or precedes {}",
+                    b.range,
+                    a.range
+                ));
+            }
+        }
+        for range in &self.ranges {
+            if let Some(confidence) = range.confidence {
+                if !(0.0..=1.0).contains(&confidence) {
+                    return Err(anyhow::anyhow!(
+                        "Range {} has confidence {} outside [0, 1]",
+                        range.range,
+                        confidence
+                    ));
+                }
+            }
+        }
+        if let (Some(provenance), Some(last)) = (&self.provenance, self.ranges.last()) {
+            if *last.range.end() > provenance.source.duration {
+                return Err(anyhow::anyhow!(
+                    "Range {} ends after the video at {}",
+                    last.range,
+                    provenance.source.duration
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Version 2 of the on-disk format, the current one.
+#[derive(Deserialize)]
+struct ClipsFileV2 {
+    #[serde(default)]
+    provenance: Option<Provenance>,
+    ranges: Vec<DetectedRange>,
+}
+
+/// The original format, without a `version`.
+#[derive(Deserialize)]
+struct ClipsFileV1 {
+    ranges: Vec<TimeRange>,
+    #[serde(default)]
+    warnings: Vec<LegacyRangeWarning>,
+}
+
+#[derive(Deserialize)]
+struct LegacyRangeWarning {
+    range: usize,
+    flag: BoundaryFlag,
+}
+
+impl From<ClipsFileV1> for ClipsInfo {
+    fn from(file: ClipsFileV1) -> Self {
+        let mut ranges = file
+            .ranges
+            .into_iter()
+            .map(DetectedRange::new)
+            .collect::<Vec<_>>();
+        for warning in file.warnings {
+            if let Some(range) = ranges.get_mut(warning.range) {
+                range.flags.push(warning.flag);
+            }
+        }
+        Self::new(ranges, None)
+    }
+}
+
+/// Reads any version of the file by its `version`, rejecting versions this build doesn't
+/// know rather than guessing at their layout.
+impl<'de> Deserialize<'de> for ClipsInfo {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = serde_json::Value::deserialize(deserializer)?;
+        let version = match value.get("version") {
+            None => None,
+            Some(version) => Some(
+                version
+                    .as_u64()
+                    .ok_or_else(|| de::Error::custom(format!("Invalid version {}", version)))?,
+            ),
+        };
+        match version {
+            None => serde_json::from_value::<ClipsFileV1>(value).map(Self::from),
+            Some(2) => serde_json::from_value::<ClipsFileV2>(value).map(|file| Self {
+                version: 2,
+                provenance: file.provenance,
+                ranges: file.ranges,
+            }),
+            Some(version) => {
+                return Err(de::Error::custom(format!(
+                    "Unsupported clips schema version {}",
+                    version
+                )))
+            }
+        }
+        .map_err(de::Error::custom)
+    }
+}
+
+const QUICK_HASH_CHUNK: u64 = 4 << 20;
+
+/// Hashes the size and the first and last 4 MiB of a file, which tells re-downloads and
+/// re-encodes apart without reading whole videos.
+pub fn quick_file_hash<P: AsRef<Path>>(path: P) -> anyhow::Result<String> {
+    let mut file = File::open(path)?;
+    let size = file.metadata()?.len();
+
+    let mut hasher = Sha256::new();
+    hasher.update(size.to_le_bytes());
+    let mut buf = Vec::new();
+    (&mut file).take(QUICK_HASH_CHUNK).read_to_end(&mut buf)?;
+    hasher.update(&buf);
+    if size > QUICK_HASH_CHUNK * 2 {
+        file.seek(SeekFrom::End(-(QUICK_HASH_CHUNK as i64)))?;
+        buf.clear();
+        file.take(QUICK_HASH_CHUNK).read_to_end(&mut buf)?;
+        hasher.update(&buf);
+    }
+
+    let digest = hasher.finalize();
+    Ok(format!(
+        "quick-sha256:{}",
+        digest
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<String>()
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_migrate_v1() {
+        let info: ClipsInfo = serde_json::from_str(
+            r#"{"ranges":[[{"seconds":387,"milliseconds":417},{"seconds":423,"milliseconds":350}],[{"seconds":290,"milliseconds":317},{"seconds":319,"milliseconds":383}]],"warnings":[{"range":0,"flag":"end_at_video_end"}]}"#,
+        )
+        .unwrap();
+        assert_eq!(info.version, CLIPS_SCHEMA_VERSION);
+        assert!(!info.is_analysed());
+        assert_eq!(info.ranges.len(), 2);
+        assert_eq!(
+            *info.ranges[0].range.start(),
+            VideoTimestamp::from_millis(290_317)
+        );
+        assert_eq!(info.ranges[1].flags, vec![BoundaryFlag::EndAtVideoEnd]);
+
+        let empty: ClipsInfo = serde_json::from_str(r#"{"ranges":[]}"#).unwrap();
+        assert!(empty.ranges.is_empty() && !empty.is_analysed());
+
+        for unsupported in [
+            r#"{"version":3,"ranges":[]}"#,
+            r#"{"version":1,"ranges":[]}"#,
+            r#"{"version":"2","ranges":[]}"#,
+        ] {
+            assert!(serde_json::from_str::<ClipsInfo>(unsupported).is_err());
+        }
+    }
+
+    #[test]
+    fn test_roundtrip_v2() {
+        let range = TimeRange::new(
+            VideoTimestamp::from_seconds(1),
+            VideoTimestamp::from_seconds(5),
+        )
+        .unwrap();
+        let info = ClipsInfo::new(
+            vec![DetectedRange {
+                range,
+                confidence: Some(0.9),
+                flags: vec![BoundaryFlag::BeginAtVideoStart],
+            }],
+            Some(Provenance {
+                detector: "test".into(),
+                detector_version: "0".into(),
+                parameters: serde_json::json!({ "enter": 0.6 }),
+                source: SourceInfo {
+                    duration: VideoTimestamp::from_seconds(60),
+                    width: Some(1920),
+                    height: Some(1080),
+                    frame_rate: Some((30, 1)),
+                    hash: None,
+                },
+                analyzed_at: Utc::now(),
+            }),
+        );
+        let dir = std::env::temp_dir().join(format!("clips-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        info.save(dir.join("id.json")).unwrap();
+        let parsed = ClipsInfo::load(dir.join("id.json")).unwrap();
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(parsed.is_analysed());
+        assert_eq!(parsed.ranges, info.ranges);
+        parsed.validate().unwrap();
+
+        let mut past_end = parsed.clone();
+        past_end.ranges[0].range = TimeRange::new(
+            VideoTimestamp::from_seconds(50),
+            VideoTimestamp::from_seconds(70),
+        )
+        .unwrap();
+        assert!(past_end.validate().is_err());
+
+        let mut overlapping = parsed.clone();
+        overlapping.provenance = None;
+        overlapping.ranges.push(overlapping.ranges[0].clone());
+        assert!(overlapping.validate().is_err());
+    }
+
+    #[test]
+    fn test_committed_dataset_parses() {
+        for entry in std::fs::read_dir("clips").unwrap() {
+            let path = entry.unwrap().path();
+            ClipsInfo::load(&path)
+                .and_then(|info| info.validate())
+                .unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+        }
+    }
+}
@@ -0,0 +1,75 @@
+use std::path::{Path, PathBuf};
+
+use crate::{
+    clips::ClipsInfo,
+    diff::{diff_ranges, RangeChange},
+};
+
+/// Where `find-clips` writes its results.
+pub const WORKSPACE_CLIPS_DIR: &str = "data/clips";
+/// The curated range files committed to the repository.
+pub const DATASET_CLIPS_DIR: &str = "clips";
+
+pub fn clips_file<P: AsRef<Path>>(dir: P, id: &str) -> PathBuf {
+    dir.as_ref().join(format!("{}.json", id))
+}
+
+/// Ids of all range files in `dir`, sorted.
+pub fn list_clip_ids<P: AsRef<Path>>(dir: P) -> anyhow::Result<Vec<String>> {
+    let mut ids = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "json") {
+            // hidden ones are partial writes of a save that never finished
+            if let Some(stem) = path.file_stem().and_then(|s| s.to_str()) {
+                if stem.starts_with('.') {
+                    continue;
+                }
+                ids.push(stem.to_string());
+            }
+        }
+    }
+    ids.sort();
+    Ok(ids)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SyncOutcome {
+    Unchanged,
+    Created,
+    Updated(Vec<RangeChange>),
+    /// The destination has different ranges and overwriting was not allowed.
+    Conflict(Vec<RangeChange>),
+}
+
+/// Validates `info` and copies it to `dest` unless the ranges there are the same. With `write`
+/// unset only the outcome is reported.
+pub fn sync_clips<P: AsRef<Path>>(
+    info: &ClipsInfo,
+    dest: P,
+    overwrite: bool,
+    write: bool,
+) -> anyhow::Result<SyncOutcome> {
+    info.validate()?;
+    let dest = dest.as_ref();
+    let outcome = if dest.exists() {
+        let existing = ClipsInfo::load(dest)?;
+        let changes = diff_ranges(&existing.intervals(), &info.intervals());
+        if changes.is_empty() {
+            SyncOutcome::Unchanged
+        } else if overwrite {
+            SyncOutcome::Updated(changes)
+        } else {
+            SyncOutcome::Conflict(changes)
+        }
+    } else {
+        SyncOutcome::Created
+    };
+    if write && matches!(outcome, SyncOutcome::Created | SyncOutcome::Updated(_)) {
+        if let Some(parent) = dest.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        info.save(dest)?;
+    }
+    Ok(outcome)
+}
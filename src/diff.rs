@@ -0,0 +1,210 @@
+use std::{collections::BTreeSet, path::Path, time::Duration};
+
+use serde::Serialize;
+
+use crate::{
+    clips::ClipsInfo,
+    dataset::{clips_file, list_clip_ids},
+    interval::{IntervalSet, TimeRange},
+};
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "change", rename_all = "snake_case")]
+pub enum RangeChange {
+    Added { range: TimeRange },
+    Removed { range: TimeRange },
+    Shifted { old: TimeRange, new: TimeRange },
+}
+
+impl RangeChange {
+    /// Signed movement of the start and end boundary in microseconds, zero for added and
+    /// removed ranges.
+    pub fn shift_micros(&self) -> (i64, i64) {
+        match self {
+            RangeChange::Shifted { old, new } => (
+                new.start().micros_since(old.start()),
+                new.end().micros_since(old.end()),
+            ),
+            _ => (0, 0),
+        }
+    }
+
+    /// Whether the change is worth reporting: any addition or removal, or a shift of either
+    /// boundary by more than `threshold`.
+    pub fn exceeds(&self, threshold: Duration) -> bool {
+        let (start, end) = self.shift_micros();
+        match self {
+            RangeChange::Shifted { .. } => {
+                start.unsigned_abs().max(end.unsigned_abs()) > threshold.as_micros() as u64
+            }
+            _ => true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DiffSide {
+    Old,
+    New,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct VideoDiff {
+    pub video_id: String,
+    /// Set when the video has a range file on one side only.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub missing: Option<DiffSide>,
+    pub changes: Vec<RangeChange>,
+}
+
+/// Compares the range files of two directories, returning the videos with changes exceeding
+/// `threshold`. A file present on one side only is compared against no ranges.
+pub fn diff_dirs<P: AsRef<Path>, Q: AsRef<Path>>(
+    old: P,
+    new: Q,
+    threshold: Duration,
+) -> anyhow::Result<Vec<VideoDiff>> {
+    let (old, new) = (old.as_ref(), new.as_ref());
+    let old_ids = list_clip_ids(old)?;
+    let new_ids = list_clip_ids(new)?;
+    let load = |dir: &Path, ids: &[String], id: &str| -> anyhow::Result<Option<IntervalSet>> {
+        if !ids.iter().any(|x| x == id) {
+            return Ok(None);
+        }
+        let path = clips_file(dir, id);
+        ClipsInfo::load(&path)
+            .map(|info| Some(info.intervals()))
+            .map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))
+    };
+
+    let mut diffs = Vec::new();
+    for id in old_ids
+        .iter()
+        .chain(new_ids.iter())
+        .collect::<BTreeSet<_>>()
+    {
+        let old_ranges = load(old, &old_ids, id)?;
+        let new_ranges = load(new, &new_ids, id)?;
+        let missing = match (&old_ranges, &new_ranges) {
+            (None, _) => Some(DiffSide::Old),
+            (_, None) => Some(DiffSide::New),
+            _ => None,
+        };
+        let changes = diff_ranges(
+            &old_ranges.unwrap_or_default(),
+            &new_ranges.unwrap_or_default(),
+        )
+        .into_iter()
+        .filter(|c| c.exceeds(threshold))
+        .collect::<Vec<_>>();
+        if !changes.is_empty() || missing.is_some() {
+            diffs.push(VideoDiff {
+                video_id: id.clone(),
+                missing,
+                changes,
+            });
+        }
+    }
+    Ok(diffs)
+}
+
+/// Pairs every old range with the first new range it overlaps, reporting pairs that differ as
+/// shifted and everything left unpaired as added or removed.
+pub fn diff_ranges(old: &IntervalSet, new: &IntervalSet) -> Vec<RangeChange> {
+    let old = old.iter().collect::<Vec<_>>();
+    let new = new.iter().collect::<Vec<_>>();
+    let mut changes = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() && j < new.len() {
+        let (a, b) = (old[i], new[j]);
+        if a.overlaps(b) {
+            if a != b {
+                changes.push(RangeChange::Shifted {
+                    old: a.clone(),
+                    new: b.clone(),
+                });
+            }
+            i += 1;
+            j += 1;
+        } else if a.end() <= b.start() {
+            changes.push(RangeChange::Removed { range: a.clone() });
+            i += 1;
+        } else {
+            changes.push(RangeChange::Added { range: b.clone() });
+            j += 1;
+        }
+    }
+    changes.extend(
+        old[i..]
+            .iter()
+            .map(|&r| RangeChange::Removed { range: r.clone() }),
+    );
+    changes.extend(
+        new[j..]
+            .iter()
+            .map(|&r| RangeChange::Added { range: r.clone() }),
+    );
+    changes
+}
+
+impl std::fmt::Display for RangeChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RangeChange::Added { range } => write!(f, "+ {}", range),
+            RangeChange::Removed { range } => write!(f, "- {}", range),
+            RangeChange::Shifted { old, new } => {
+                let (start, end) = self.shift_micros();
+                write!(
+                    f,
+                    "~ {} -> {} ({:+.3}s, {:+.3}s)",
+                    old,
+                    new,
+                    start as f64 / 1e6,
+                    end as f64 / 1e6
+                )
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ffmpeg::VideoTimestamp;
+
+    fn range(start: u64, end: u64) -> TimeRange {
+        TimeRange::new(
+            VideoTimestamp::from_seconds(start),
+            VideoTimestamp::from_seconds(end),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_diff_ranges() {
+        let old = IntervalSet::from(vec![range(10, 20), range(30, 40), range(100, 110)]);
+        let new = IntervalSet::from(vec![range(10, 20), range(31, 40), range(50, 60)]);
+        let changes = diff_ranges(&old, &new);
+        assert_eq!(
+            changes,
+            vec![
+                RangeChange::Shifted {
+                    old: range(30, 40),
+                    new: range(31, 40)
+                },
+                RangeChange::Added {
+                    range: range(50, 60)
+                },
+                RangeChange::Removed {
+                    range: range(100, 110)
+                },
+            ]
+        );
+        assert_eq!(changes[0].shift_micros(), (1_000_000, 0));
+        assert!(changes[0].exceeds(Duration::from_millis(500)));
+        assert!(!changes[0].exceeds(Duration::from_secs(1)));
+        assert!(changes[1].exceeds(Duration::from_secs(1000)));
+        assert!(diff_ranges(&new, &new).is_empty());
+    }
+}
@@ -1,29 +1,71 @@
-use std::process::Command;
+use std::{path::Path, process::Command};
 
-use log::warn;
+use log::{info, warn};
+use regex::Regex;
 
-pub fn download_video(url: &str, output: &str) -> anyhow::Result<()> {
+use crate::{
+    cancel::{CancelToken, Cancelled},
+    ffmpeg::{process::Stop, scheduler::scheduler},
+};
+
+pub fn download_video(url: &str, output: &str, cancel: &CancelToken) -> anyhow::Result<()> {
+    cancel.check()?;
     let mut cmd = Command::new("/usr/bin/python");
     cmd.arg("-m").arg("youtube_dl");
-    cmd.arg("-o").arg(format!("{}", output));
+    cmd.arg("-o").arg(output);
     cmd.arg("-f").arg("mp4[height=1080]+bestaudio");
     cmd.arg(url);
 
-    cmd.stderr(std::process::Stdio::inherit());
-    cmd.stdout(std::process::Stdio::inherit());
+    if scheduler().dry_run() {
+        scheduler().record(&cmd, false);
+        return Ok(());
+    }
 
-    if !cmd.spawn()?.wait()?.success() {
+    // youtube-dl redraws its progress bar in place
+    let outcome = scheduler().runner().run_inherited(&mut cmd, cancel, None)?;
+    if outcome.stop == Some(Stop::Cancelled) {
+        remove_partial_downloads(output)?;
+        return Err(Cancelled.into());
+    }
+    if !outcome.status.success() {
         return Err(anyhow::anyhow!("Failed to download video"));
     }
 
     Ok(())
 }
 
-pub fn download_video_with_retries(url: &str, output: &str, retries: usize) -> anyhow::Result<()> {
+/// Removes the `.part` files and separately downloaded formats (`{output}.f137.mp4`) that
+/// youtube-dl leaves when interrupted.
+fn remove_partial_downloads(output: &str) -> anyhow::Result<()> {
+    let output = Path::new(output);
+    let (Some(dir), Some(name)) = (output.parent(), output.file_name()) else {
+        return Ok(());
+    };
+    let pattern = Regex::new(&format!(
+        r"^{}\.((.*\.)?part|(.*\.)?ytdl|f\d+\..*)$",
+        regex::escape(&name.to_string_lossy())
+    ))
+    .unwrap();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if pattern.is_match(&path.file_name().unwrap().to_string_lossy()) {
+            std::fs::remove_file(path)?;
+        }
+    }
+    Ok(())
+}
+
+pub fn download_video_with_retries(
+    url: &str,
+    output: &str,
+    retries: usize,
+    cancel: &CancelToken,
+) -> anyhow::Result<()> {
     for i in 0..retries {
-        println!("Downloading video {} (attempt {})", url, i + 1);
-        match download_video(url, output) {
+        info!("Downloading video {} (attempt {})", url, i + 1);
+        match download_video(url, output, cancel) {
             Ok(()) => return Ok(()),
+            Err(e) if e.is::<Cancelled>() => return Err(e),
             Err(e) => {
                 warn!("Failed to download video: {}", e);
                 std::thread::sleep(std::time::Duration::from_secs(5));
@@ -32,3 +74,30 @@ pub fn download_video_with_retries(url: &str, output: &str, retries: usize) -> a
     }
     Err(anyhow::anyhow!("Failed to download video"))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ffmpeg::scheduler::test_replay, runner::Recording};
+
+    #[test]
+    fn test_download_video() {
+        let command = "/usr/bin/python -m youtube_dl -o data/videos/replay.mp4 \
+                       -f 'mp4[height=1080]+bestaudio' https://youtu.be/replay";
+        test_replay().push(Recording {
+            code: Some(1),
+            ..Recording::success(command, &["ERROR: Unable to download webpage"])
+        });
+        test_replay().push(Recording::success(command, &["[download] 100%"]));
+        let download = || {
+            download_video(
+                "https://youtu.be/replay",
+                "data/videos/replay.mp4",
+                &CancelToken::new(),
+            )
+        };
+        assert!(download().is_err());
+        assert!(download().is_ok());
+        assert!(download().is_err());
+    }
+}
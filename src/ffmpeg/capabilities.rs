@@ -0,0 +1,193 @@
+use std::{collections::BTreeSet, sync::OnceLock};
+
+use super::{encoder::EncoderProfile, process::job_name, scheduler::scheduler};
+
+/// Oldest ffmpeg release whose options this crate relies on.
+pub const MIN_FFMPEG_MAJOR_VERSION: u32 = 4;
+
+/// What the installed ffmpeg was built with.
+#[derive(Debug, Clone, Default)]
+pub struct Capabilities {
+    pub version: String,
+    pub encoders: BTreeSet<String>,
+    pub filters: BTreeSet<String>,
+    pub hwaccels: BTreeSet<String>,
+}
+
+static CAPABILITIES: OnceLock<Result<Capabilities, String>> = OnceLock::new();
+
+/// Detects the capabilities of ffmpeg once per run.
+pub fn capabilities() -> anyhow::Result<&'static Capabilities> {
+    CAPABILITIES
+        .get_or_init(|| Capabilities::detect().map_err(|e| e.to_string()))
+        .as_ref()
+        .map_err(|e| anyhow::anyhow!("{}", e))
+}
+
+fn ffmpeg_output(arg: &str) -> anyhow::Result<String> {
+    let mut cmd = scheduler().ffmpeg();
+    cmd.arg("-hide_banner").arg(arg);
+    let stdout = scheduler()
+        .query(&mut cmd, &job_name("ffmpeg", arg))
+        .map_err(|e| anyhow::anyhow!("Failed to run ffmpeg, is it installed? {}", e))?;
+    Ok(String::from_utf8_lossy(&stdout).into_owned())
+}
+
+impl Capabilities {
+    pub fn detect() -> anyhow::Result<Self> {
+        let capabilities = Self {
+            version: parse_version(&ffmpeg_output("-version")?)
+                .ok_or_else(|| anyhow::anyhow!("Unrecognized ffmpeg -version output"))?,
+            encoders: parse_encoders(&ffmpeg_output("-encoders")?),
+            filters: parse_filters(&ffmpeg_output("-filters")?),
+            hwaccels: parse_hwaccels(&ffmpeg_output("-hwaccels")?),
+        };
+        match capabilities.major_version() {
+            Some(major) if major < MIN_FFMPEG_MAJOR_VERSION => Err(anyhow::anyhow!(
+                "ffmpeg {} is too old, at least {} is needed",
+                capabilities.version,
+                MIN_FFMPEG_MAJOR_VERSION
+            )),
+            _ => Ok(capabilities),
+        }
+    }
+
+    /// Major version, if the version string is a release number rather than a git build.
+    pub fn major_version(&self) -> Option<u32> {
+        let version = self.version.trim_start_matches('n');
+        version.split('.').next()?.parse().ok()
+    }
+
+    pub fn has_encoder(&self, name: &str) -> bool {
+        self.encoders.contains(name)
+    }
+
+    pub fn has_filter(&self, name: &str) -> bool {
+        self.filters.contains(name)
+    }
+
+    pub fn has_hwaccel(&self, name: &str) -> bool {
+        self.hwaccels.contains(name)
+    }
+
+    /// Checks that ffmpeg has everything `profile` asks for.
+    pub fn check_profile(&self, profile: &EncoderProfile) -> anyhow::Result<()> {
+        for codec in [&profile.video_codec, &profile.audio_codec] {
+            if codec != "copy" && !self.has_encoder(codec) {
+                return Err(anyhow::anyhow!(
+                    "ffmpeg {} has no {} encoder",
+                    self.version,
+                    codec
+                ));
+            }
+        }
+        if let Some(ref hwaccel) = profile.hwaccel {
+            if !self.has_hwaccel(hwaccel) {
+                return Err(anyhow::anyhow!(
+                    "ffmpeg {} has no {} hardware acceleration",
+                    self.version,
+                    hwaccel
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn check_filter(&self, name: &str) -> anyhow::Result<()> {
+        if !self.has_filter(name) {
+            return Err(anyhow::anyhow!(
+                "ffmpeg {} has no {} filter",
+                self.version,
+                name
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// `ffmpeg version 6.1.1 Copyright ...` -> `6.1.1`
+fn parse_version(output: &str) -> Option<String> {
+    let mut words = output.lines().next()?.split_whitespace();
+    if words.next()? != "ffmpeg" || words.next()? != "version" {
+        return None;
+    }
+    words.next().map(|v| v.to_string())
+}
+
+/// Names from the ` V....D libx264  description` lines below the `------` separator.
+fn parse_encoders(output: &str) -> BTreeSet<String> {
+    output
+        .lines()
+        .skip_while(|line| !line.trim_start().starts_with("---"))
+        .skip(1)
+        .filter_map(|line| line.split_whitespace().nth(1))
+        .map(|name| name.to_string())
+        .collect()
+}
+
+/// Names from the ` T.C subtitles  V->V  description` lines.
+fn parse_filters(output: &str) -> BTreeSet<String> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut words = line.split_whitespace();
+            let (_flags, name, io) = (words.next()?, words.next()?, words.next()?);
+            io.contains("->").then(|| name.to_string())
+        })
+        .collect()
+}
+
+fn parse_hwaccels(output: &str) -> BTreeSet<String> {
+    output
+        .lines()
+        .skip_while(|line| !line.starts_with("Hardware acceleration methods"))
+        .skip(1)
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty())
+        .map(|line| line.to_string())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ffmpeg::encoder::builtin_profiles;
+
+    #[test]
+    fn test_parse_capabilities() {
+        let capabilities = Capabilities {
+            version: parse_version("ffmpeg version n6.1.1 Copyright (c) 2000-2023\nbuilt with gcc")
+                .unwrap(),
+            encoders: parse_encoders(
+                "Encoders:\n V..... = Video\n A..... = Audio\n ------\n \
+                 V....D libx264              libx264 H.264 / AVC (codec h264)\n \
+                 A....D aac                  AAC (Advanced Audio Coding)\n",
+            ),
+            filters: parse_filters(
+                "Filters:\n  T.. = Timeline support\n  A = Audio input/output\n  \
+                 | = Source or sink filter\n \
+                 ... abench            A->A       Benchmark part of a filtergraph.\n \
+                 TSC select            V->N       Select video frames to pass in output.\n",
+            ),
+            hwaccels: parse_hwaccels("Hardware acceleration methods:\nvdpau\nvaapi\n\n"),
+        };
+        assert_eq!(capabilities.version, "n6.1.1");
+        assert_eq!(capabilities.major_version(), Some(6));
+        assert_eq!(
+            capabilities.encoders.iter().collect::<Vec<_>>(),
+            ["aac", "libx264"]
+        );
+        assert_eq!(
+            capabilities.filters.iter().collect::<Vec<_>>(),
+            ["abench", "select"]
+        );
+        assert!(capabilities.has_hwaccel("vaapi"));
+
+        let profiles = builtin_profiles();
+        assert!(capabilities.check_profile(&profiles["x264"]).is_ok());
+        assert!(capabilities.check_profile(&profiles["copy"]).is_ok());
+        assert!(capabilities.check_profile(&profiles["vp9"]).is_err());
+        assert!(capabilities.check_profile(&profiles["nvenc"]).is_err());
+        assert!(capabilities.check_filter("subtitles").is_err());
+    }
+}
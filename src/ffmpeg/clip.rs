@@ -1,63 +1,206 @@
-use std::process::Command;
+use super::{
+    concat::concat_videos,
+    encoder::EncoderProfile,
+    filter::{Chain, Filter},
+    process::{job_name, PartialOutput},
+    progress::{progress_args, run_with_progress, Progress},
+    scheduler::scheduler,
+    VideoTimestamp,
+};
+use crate::interval::IntervalSet;
 
-use super::VideoTimestamp;
-
-pub fn make_multiple_clip<I>(
+/// Joins `ranges` of `input` into one video. Progress is reported against the total duration
+/// of the ranges. `output` only appears once it is complete.
+pub fn make_multiple_clip<P>(
     input: &str,
     output: &str,
-    ranges: I,
-    cuda: bool,
+    ranges: &IntervalSet,
+    profile: &EncoderProfile,
     overwrite: bool,
+    on_progress: P,
 ) -> anyhow::Result<()>
 where
-    I: IntoIterator<Item = (VideoTimestamp, VideoTimestamp)>,
+    P: FnMut(&Progress) + Send,
 {
-    let mut cmd = Command::new("ffmpeg");
-    if cuda {
-        cmd.arg("-hwaccel").arg("cuda");
+    if !overwrite && std::path::Path::new(output).exists() {
+        return Err(anyhow::anyhow!("{} already exists", output));
     }
-
-    if overwrite {
-        cmd.arg("-y");
+    if profile.is_copy() {
+        return copy_multiple_clip(input, output, ranges, on_progress);
     }
 
+    let partial = PartialOutput::new(output)?;
+    let mut cmd = scheduler().ffmpeg();
+    progress_args(&mut cmd);
+    profile.input_args(&mut cmd);
+    cmd.arg("-y");
+
     cmd.arg("-i").arg(input);
 
-    let mut vf = String::from("select='");
-    let mut af = String::from("aselect='");
-
-    for (i, (begin, end)) in ranges.into_iter().enumerate() {
-        if i > 0 {
-            vf.push_str("+");
-            af.push_str("+");
-        }
-        vf.push_str(&format!(
-            "between(t,{:.3},{:.3})",
-            begin.as_float_seconds(),
-            end.as_float_seconds()
-        ));
-        af.push_str(&format!(
-            "between(t,{:.3},{:.3})",
-            begin.as_float_seconds(),
-            end.as_float_seconds()
-        ));
-    }
+    let selected = ranges
+        .iter()
+        .map(|range| {
+            format!(
+                "between(t,{:.3},{:.3})",
+                range.start().as_float_seconds(),
+                range.end().as_float_seconds()
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("+");
+    let vf = Chain::new()
+        .filter(Filter::new("select").arg(&selected))
+        .filter(Filter::new("setpts").arg("N/FRAME_RATE/TB"));
+    let af = Chain::new()
+        .filter(Filter::new("aselect").arg(&selected))
+        .filter(Filter::new("asetpts").arg("N/SR/TB"));
 
-    vf.push_str("',setpts=N/FRAME_RATE/TB");
-    af.push_str("',asetpts=N/SR/TB");
+    cmd.arg("-vf").arg(vf.to_string());
+    cmd.arg("-af").arg(af.to_string());
 
-    cmd.arg("-vf").arg(vf);
-    cmd.arg("-af").arg(af);
+    profile.output_args(&mut cmd);
 
-    if cuda {
-        cmd.arg("-c:v").arg("h264_nvenc");
-    }
+    cmd.arg(partial.path());
 
-    cmd.arg(output);
+    run_with_progress(&mut cmd, &job_name("clip", output), on_progress)?;
 
-    if !cmd.spawn()?.wait()?.success() {
-        return Err(anyhow::anyhow!("Failed to make clip"));
-    }
+    partial.commit()?;
+    Ok(())
+}
+
+/// Cuts every range without re-encoding and joins the pieces. Cuts snap to the keyframe at or
+/// before each range start, so clips may begin slightly early.
+fn copy_multiple_clip<P>(
+    input: &str,
+    output: &str,
+    ranges: &IntervalSet,
+    mut on_progress: P,
+) -> anyhow::Result<()>
+where
+    P: FnMut(&Progress) + Send,
+{
+    let parts_dir = format!("{}.parts", output);
+    scheduler().create_dir_all(&parts_dir)?;
 
+    let result = cut_parts(input, ranges, &parts_dir, &mut on_progress)
+        .and_then(|parts| concat_videos(&parts, output, true));
+    // the parts are worthless once interrupted, nothing resumes from them
+    let _ = scheduler().remove_dir_all(&parts_dir);
+    result?;
+
+    on_progress(&Progress {
+        out_time: ranges.total_duration().into(),
+        finished: true,
+        ..Progress::default()
+    });
     Ok(())
 }
+
+fn cut_parts<P>(
+    input: &str,
+    ranges: &IntervalSet,
+    parts_dir: &str,
+    on_progress: &mut P,
+) -> anyhow::Result<Vec<String>>
+where
+    P: FnMut(&Progress) + Send,
+{
+    let mut parts = Vec::new();
+    let mut done = VideoTimestamp::zero();
+    for (i, range) in ranges.iter().enumerate() {
+        let part = format!("{}/part{:03}.mkv", parts_dir, i);
+        let mut cmd = scheduler().ffmpeg();
+        progress_args(&mut cmd);
+        cmd.arg("-y");
+        cmd.arg("-ss").arg(range.start().as_ffmpeg_arg());
+        cmd.arg("-to").arg(range.end().as_ffmpeg_arg());
+        cmd.arg("-i").arg(input);
+        cmd.arg("-map").arg("0");
+        cmd.arg("-c").arg("copy");
+        cmd.arg("-avoid_negative_ts").arg("make_zero");
+        cmd.arg(&part);
+        run_with_progress(&mut cmd, &job_name("cut", &part), |progress| {
+            on_progress(&Progress {
+                finished: false,
+                ..progress.offset(done)
+            })
+        })
+        .map_err(|e| e.context(format!("Failed to cut {}", range)))?;
+        done = done + range.duration();
+        parts.push(part);
+    }
+    Ok(parts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        ffmpeg::{encoder::builtin_profiles, scheduler::test_replay, VideoTimestamp as Ts},
+        interval::TimeRange,
+        runner::Recording,
+    };
+
+    #[test]
+    fn test_copy_multiple_clip() {
+        let dir = std::env::temp_dir().join(format!("clip-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let output = dir.join("clip.mkv").to_string_lossy().into_owned();
+        let parts = format!("{}.parts", output);
+        let cut = |i: usize, start: &str, end: &str, micros: u64| {
+            let part = format!("{}/part{:03}.mkv", parts, i);
+            Recording::success(
+                &format!(
+                    "ffmpeg -nostats -progress pipe:1 -y -ss {} -to {} -i in.mp4 -map 0 -c copy \
+                     -avoid_negative_ts make_zero {}",
+                    start, end, part
+                ),
+                &[format!("out_time_us={}", micros), "progress=end".into()],
+            )
+            .with_outputs(&[part])
+        };
+        test_replay().push(cut(0, "00:00:01.000", "00:00:03.000", 2_000_000));
+        test_replay().push(cut(1, "00:00:10.000", "00:00:11.500", 1_500_000));
+        let partial = dir.join(".clip.partial.mkv");
+        test_replay().push(
+            Recording::success(
+                &format!(
+                    "ffmpeg -y -f concat -safe 0 -i {}.txt -fflags +igndts -c copy {}",
+                    output,
+                    partial.display()
+                ),
+                &[] as &[&str],
+            )
+            .with_outputs(&[&partial]),
+        );
+
+        let ranges = IntervalSet::from_ranges([
+            TimeRange::new(Ts::from_millis(1000), Ts::from_millis(3000)).unwrap(),
+            TimeRange::new(Ts::from_millis(10000), Ts::from_millis(11500)).unwrap(),
+        ]);
+        let mut progress = Vec::new();
+        make_multiple_clip(
+            "in.mp4",
+            &output,
+            &ranges,
+            &builtin_profiles()["copy"],
+            false,
+            |p: &Progress| progress.push((p.out_time, p.finished)),
+        )
+        .unwrap();
+        assert_eq!(
+            progress,
+            [
+                (Ts::from_millis(2000), false),
+                (Ts::from_millis(3500), false),
+                (Ts::from_millis(3500), true),
+            ]
+        );
+        assert!(std::path::Path::new(&output).exists());
+        assert!(!std::path::Path::new(&parts).exists());
+        assert!(!partial.exists());
+        assert!(!std::path::Path::new(&format!("{}.txt", output)).exists());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,82 +1,122 @@
-use std::{io::Write, process::Command};
+use std::path::Path;
 
+use super::{
+    encoder::EncoderProfile,
+    filter::{Chain, Filter, FilterGraph},
+    process::{job_name, PartialOutput},
+    progress::{progress_args, run_with_progress, Progress},
+    scheduler::scheduler,
+};
+
+/// Joins `inputs` with the concat demuxer, replacing `output` once it is complete.
 pub fn concat_videos<I>(inputs: I, output: &str, copy: bool) -> anyhow::Result<()>
 where
     I: IntoIterator,
     I::Item: AsRef<str>,
 {
+    let partial = PartialOutput::new(output)?;
     let filelist_path = format!("{}.txt", output);
-    let mut filelist = std::fs::File::create(&filelist_path)?;
+    let filelist_dir = Path::new(&filelist_path).parent().unwrap_or(Path::new(""));
+    let mut filelist = String::new();
     for input in inputs {
-        let abs_path = std::fs::canonicalize(input.as_ref())?;
-        writeln!(filelist, "file '{}'", abs_path.display())?;
+        // the demuxer resolves relative paths against the list, which keeps a planned list
+        // usable on another machine. Made absolute rather than canonical otherwise, as in a dry
+        // run the inputs may not exist yet
+        let input = Path::new(input.as_ref());
+        let path = match input.strip_prefix(filelist_dir) {
+            Ok(relative) if input.is_relative() == filelist_dir.is_relative() => {
+                relative.to_path_buf()
+            }
+            _ => std::path::absolute(input)?,
+        };
+        filelist.push_str(&format!("file '{}'\n", path.display()));
     }
+    scheduler().write_file(&filelist_path, &filelist)?;
 
-    let mut cmd = Command::new("ffmpeg");
+    let mut cmd = scheduler().ffmpeg();
+    cmd.arg("-y");
     cmd.arg("-f").arg("concat");
     cmd.arg("-safe").arg("0");
-    cmd.arg("-i").arg(filelist_path);
+    cmd.arg("-i").arg(&filelist_path);
     cmd.arg("-fflags").arg("+igndts");
     if copy {
         cmd.arg("-c").arg("copy");
     }
-    cmd.arg(output);
-
-    cmd.stderr(std::process::Stdio::inherit());
-    cmd.stdout(std::process::Stdio::inherit());
+    cmd.arg(partial.path());
 
-    if !cmd.spawn()?.wait()?.success() {
-        return Err(anyhow::anyhow!("Failed to concat videos"));
-    }
+    let result = scheduler().run(&mut cmd, &job_name("concat", output), |line| {
+        eprintln!("{}", line)
+    });
+    // a planned run still needs it, so the plan removes it after the command instead
+    let _ = scheduler().remove_file(&filelist_path);
+    result?;
 
+    partial.commit()?;
     Ok(())
 }
 
-pub fn concat_videos_filter<I>(
+pub fn concat_videos_filter<I, P>(
     inputs: I,
     output: &str,
     subtitle: Option<&str>,
-    cuda: bool,
+    profile: &EncoderProfile,
+    on_progress: P,
 ) -> anyhow::Result<()>
 where
     I: IntoIterator,
     I::Item: AsRef<str>,
+    P: FnMut(&Progress) + Send,
 {
-    let mut filter_args = String::new();
-    let mut cmd = Command::new("ffmpeg");
-
-    if cuda {
-        cmd.arg("-hwaccel").arg("cuda");
+    if profile.is_copy() {
+        return Err(anyhow::anyhow!(
+            "Concatenating with filters needs re-encoding, use concat_videos to copy streams"
+        ));
     }
 
+    let partial = PartialOutput::new(output)?;
+    let mut cmd = scheduler().ffmpeg();
+    progress_args(&mut cmd);
+    cmd.arg("-y");
+
+    profile.input_args(&mut cmd);
+
+    let mut concat = Chain::new();
     let mut n = 0;
     for (i, input) in inputs.into_iter().enumerate() {
         n += 1;
         cmd.arg("-i").arg(input.as_ref());
-        filter_args.push_str(&format!("[{}:v:0][{}:a:0]", i, i));
+        concat = concat
+            .input(&format!("{}:v:0", i))
+            .input(&format!("{}:a:0", i));
     }
-    filter_args.push_str(format!("concat=n={}:v=1:a=1[outv][outa]", n).as_str());
+    let concat = concat
+        .filter(Filter::new("concat").opt("n", n).opt("v", 1).opt("a", 1))
+        .output("outv")
+        .output("outa");
+    let mut graph = FilterGraph::from(concat);
+    let mut video_out = "[outv]";
     if let Some(subtitle) = subtitle {
-        filter_args.push_str(&format!(";[outv]subtitles={}:force_style='Alignment=1,OutlineColour=&H100000000,BorderStyle=3,Outline=1,Shadow=0,Fontsize=18'[outv]", subtitle));
+        graph = graph.chain(
+            Chain::new()
+                .input("outv")
+                .filter(Filter::new("subtitles").arg(subtitle).opt(
+                    "force_style",
+                    "Alignment=1,OutlineColour=&H100000000,BorderStyle=3,Outline=1,Shadow=0,Fontsize=18",
+                ))
+                .output("subbed"),
+        );
+        video_out = "[subbed]";
     }
-    cmd.arg("-filter_complex").arg(filter_args);
-    cmd.arg("-map").arg("[outv]");
+    cmd.arg("-filter_complex").arg(graph.to_string());
+    cmd.arg("-map").arg(video_out);
     cmd.arg("-map").arg("[outa]");
 
-    cmd.arg("-preset").arg("slow");
-
-    if cuda {
-        cmd.arg("-c:v").arg("h264_nvenc");
-    }
+    profile.output_args(&mut cmd);
 
-    cmd.arg(output);
+    cmd.arg(partial.path());
 
-    cmd.stderr(std::process::Stdio::inherit());
-    cmd.stdout(std::process::Stdio::inherit());
-
-    if !cmd.spawn()?.wait()?.success() {
-        return Err(anyhow::anyhow!("Failed to concat videos"));
-    }
+    run_with_progress(&mut cmd, &job_name("concat", output), on_progress)?;
 
+    partial.commit()?;
     Ok(())
 }
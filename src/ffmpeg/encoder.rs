@@ -0,0 +1,175 @@
+use std::{collections::BTreeMap, fs::File, path::Path, process::Command};
+
+use serde::{Deserialize, Serialize};
+
+pub const DEFAULT_ENCODER_PROFILE: &str = "x264";
+
+/// How output video and audio are encoded. `copy` as the video codec keeps the input streams,
+/// which only works without filters.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EncoderProfile {
+    /// Passed as `-hwaccel` before the inputs.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hwaccel: Option<String>,
+    pub video_codec: String,
+    /// Codec specific options such as `-crf` and `-preset`.
+    #[serde(default)]
+    pub video_args: Vec<String>,
+    pub audio_codec: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub audio_bitrate: Option<String>,
+}
+
+impl EncoderProfile {
+    fn new(video_codec: &str, video_args: &[&str], audio_codec: &str, audio_bitrate: &str) -> Self {
+        Self {
+            hwaccel: None,
+            video_codec: video_codec.into(),
+            video_args: video_args.iter().map(|s| s.to_string()).collect(),
+            audio_codec: audio_codec.into(),
+            audio_bitrate: (!audio_bitrate.is_empty()).then(|| audio_bitrate.into()),
+        }
+    }
+
+    pub fn is_copy(&self) -> bool {
+        self.video_codec == "copy"
+    }
+
+    /// Adds the options that go before `-i`.
+    pub fn input_args(&self, cmd: &mut Command) {
+        if let Some(ref hwaccel) = self.hwaccel {
+            cmd.arg("-hwaccel").arg(hwaccel);
+        }
+    }
+
+    /// Adds the codec options that go before the output file.
+    pub fn output_args(&self, cmd: &mut Command) {
+        cmd.arg("-c:v").arg(&self.video_codec);
+        cmd.args(&self.video_args);
+        cmd.arg("-c:a").arg(&self.audio_codec);
+        if let Some(ref bitrate) = self.audio_bitrate {
+            cmd.arg("-b:a").arg(bitrate);
+        }
+    }
+}
+
+pub fn builtin_profiles() -> BTreeMap<String, EncoderProfile> {
+    let mut nvenc =
+        EncoderProfile::new("h264_nvenc", &["-preset", "p5", "-cq", "23"], "aac", "192k");
+    nvenc.hwaccel = Some("cuda".into());
+    [
+        (
+            "x264",
+            EncoderProfile::new("libx264", &["-crf", "20", "-preset", "slow"], "aac", "192k"),
+        ),
+        (
+            "x265",
+            EncoderProfile::new(
+                "libx265",
+                &["-crf", "24", "-preset", "medium"],
+                "aac",
+                "192k",
+            ),
+        ),
+        (
+            "vp9",
+            EncoderProfile::new(
+                "libvpx-vp9",
+                &["-crf", "32", "-b:v", "0", "-row-mt", "1"],
+                "libopus",
+                "128k",
+            ),
+        ),
+        (
+            "svt-av1",
+            EncoderProfile::new(
+                "libsvtav1",
+                &["-crf", "35", "-preset", "8"],
+                "libopus",
+                "128k",
+            ),
+        ),
+        (
+            "aom-av1",
+            EncoderProfile::new(
+                "libaom-av1",
+                &["-crf", "30", "-b:v", "0", "-cpu-used", "6"],
+                "libopus",
+                "128k",
+            ),
+        ),
+        ("nvenc", nvenc),
+        ("copy", EncoderProfile::new("copy", &[], "copy", "")),
+    ]
+    .into_iter()
+    .map(|(name, profile)| (name.to_string(), profile))
+    .collect()
+}
+
+/// The built-in profiles, with profiles from the JSON object at `config` added or replacing
+/// them by name.
+pub fn load_profiles<P: AsRef<Path>>(
+    config: Option<P>,
+) -> anyhow::Result<BTreeMap<String, EncoderProfile>> {
+    let mut profiles = builtin_profiles();
+    if let Some(config) = config {
+        let custom: BTreeMap<String, EncoderProfile> =
+            serde_json::from_reader(File::open(config)?)?;
+        profiles.extend(custom);
+    }
+    Ok(profiles)
+}
+
+pub fn find_profile<P: AsRef<Path>>(
+    name: &str,
+    config: Option<P>,
+) -> anyhow::Result<EncoderProfile> {
+    let mut profiles = load_profiles(config)?;
+    profiles.remove(name).ok_or_else(|| {
+        anyhow::anyhow!(
+            "Unknown encoder profile {:?}, expected one of {}",
+            name,
+            profiles.keys().cloned().collect::<Vec<_>>().join(", ")
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_profile_args() {
+        let profiles = builtin_profiles();
+        let mut cmd = Command::new("ffmpeg");
+        profiles["nvenc"].input_args(&mut cmd);
+        cmd.arg("-i").arg("in.mkv");
+        profiles["nvenc"].output_args(&mut cmd);
+        assert_eq!(
+            cmd.get_args().collect::<Vec<_>>(),
+            [
+                "-hwaccel",
+                "cuda",
+                "-i",
+                "in.mkv",
+                "-c:v",
+                "h264_nvenc",
+                "-preset",
+                "p5",
+                "-cq",
+                "23",
+                "-c:a",
+                "aac",
+                "-b:a",
+                "192k"
+            ]
+        );
+        assert!(profiles["copy"].is_copy());
+        assert!(profiles[DEFAULT_ENCODER_PROFILE].hwaccel.is_none());
+
+        let custom: EncoderProfile =
+            serde_json::from_str(r#"{"video_codec":"libx264","audio_codec":"copy"}"#).unwrap();
+        assert_eq!(custom, EncoderProfile::new("libx264", &[], "copy", ""));
+        assert!(find_profile::<&str>("h266", None).is_err());
+    }
+}
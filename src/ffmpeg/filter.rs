@@ -0,0 +1,209 @@
+use std::fmt;
+
+/// A filter and its options, e.g. `select=expr`, written with every option value escaped for
+/// the filter's option parser.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Filter {
+    name: String,
+    args: Vec<(Option<String>, String)>,
+}
+
+/// A chain of filters between labelled pads, e.g. `[0:v]trim=start=1,setpts=PTS-STARTPTS[v]`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Chain {
+    inputs: Vec<String>,
+    filters: Vec<Filter>,
+    outputs: Vec<String>,
+}
+
+/// Chains separated by `;`, as passed to `-vf`, `-af` or `-filter_complex`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FilterGraph {
+    chains: Vec<Chain>,
+}
+
+/// Backslash-escapes `special` characters, and whitespace at either end which ffmpeg would
+/// otherwise trim.
+fn escape(value: &str, special: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    let last = value.chars().count().saturating_sub(1);
+    for (i, c) in value.chars().enumerate() {
+        if special.contains(c) || (c.is_whitespace() && (i == 0 || i == last)) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+impl Filter {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            args: Vec::new(),
+        }
+    }
+
+    /// Adds a positional option.
+    pub fn arg<T: ToString>(mut self, value: T) -> Self {
+        self.args.push((None, value.to_string()));
+        self
+    }
+
+    /// Adds a named option.
+    pub fn opt<T: ToString>(mut self, key: &str, value: T) -> Self {
+        self.args.push((Some(key.to_string()), value.to_string()));
+        self
+    }
+}
+
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.name)?;
+        if self.args.is_empty() {
+            return Ok(());
+        }
+        let args = self
+            .args
+            .iter()
+            .map(|(key, value)| match key {
+                Some(key) => format!("{}={}", key, escape(value, "\\':")),
+                // or the option parser would take everything before the `=` for an option name
+                None => escape(value, "\\':="),
+            })
+            .collect::<Vec<_>>()
+            .join(":");
+        // and again for the filtergraph parser, which unescapes before the filter sees them
+        write!(f, "={}", escape(&args, "\\'[],;"))
+    }
+}
+
+impl Chain {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds an input pad, e.g. `0:v:0` or a label output by another chain.
+    pub fn input(mut self, label: &str) -> Self {
+        self.inputs.push(label.to_string());
+        self
+    }
+
+    pub fn filter(mut self, filter: Filter) -> Self {
+        self.filters.push(filter);
+        self
+    }
+
+    pub fn output(mut self, label: &str) -> Self {
+        self.outputs.push(label.to_string());
+        self
+    }
+}
+
+impl fmt::Display for Chain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for input in &self.inputs {
+            write!(f, "[{}]", input)?;
+        }
+        for (i, filter) in self.filters.iter().enumerate() {
+            if i > 0 {
+                f.write_str(",")?;
+            }
+            write!(f, "{}", filter)?;
+        }
+        for output in &self.outputs {
+            write!(f, "[{}]", output)?;
+        }
+        Ok(())
+    }
+}
+
+impl FilterGraph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn chain(mut self, chain: Chain) -> Self {
+        self.chains.push(chain);
+        self
+    }
+}
+
+impl From<Chain> for FilterGraph {
+    fn from(chain: Chain) -> Self {
+        Self::new().chain(chain)
+    }
+}
+
+impl fmt::Display for FilterGraph {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, chain) in self.chains.iter().enumerate() {
+            if i > 0 {
+                f.write_str(";")?;
+            }
+            write!(f, "{}", chain)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filter_escaping() {
+        // the example from the "Notes on filtergraph escaping" section of the ffmpeg manual
+        let filter = Filter::new("drawtext").opt(
+            "text",
+            "this is a 'string': may contain one, or more, special characters",
+        );
+        assert_eq!(
+            filter.to_string(),
+            r"drawtext=text=this is a \\\'string\\\'\\: may contain one\, or more\, special characters"
+        );
+        assert_eq!(
+            Filter::new("subtitles")
+                .arg("data/it's 12:00.srt")
+                .opt("force_style", "Alignment=1,Outline=1")
+                .to_string(),
+            r"subtitles=data/it\\\'s 12\\:00.srt:force_style=Alignment=1\,Outline=1"
+        );
+        assert_eq!(
+            Filter::new("subtitles")
+                .arg("data/a=b.srt")
+                .opt("force_style", "Fontsize=24")
+                .to_string(),
+            r"subtitles=data/a\\=b.srt:force_style=Fontsize=24"
+        );
+        assert_eq!(
+            Filter::new("drawtext").opt("text", " [x] ").to_string(),
+            r"drawtext=text=\\ \[x\]\\\ "
+        );
+    }
+
+    #[test]
+    fn test_filter_graph() {
+        let graph = FilterGraph::new()
+            .chain(
+                Chain::new()
+                    .input("0:v:0")
+                    .input("1:v:0")
+                    .filter(Filter::new("concat").opt("n", 2).opt("v", 1).opt("a", 0))
+                    .output("v"),
+            )
+            .chain(
+                Chain::new()
+                    .input("v")
+                    .filter(Filter::new("select").arg("between(t,1.000,2.000)"))
+                    .filter(Filter::new("setpts").arg("N/FRAME_RATE/TB"))
+                    .filter(Filter::new("showinfo"))
+                    .output("out"),
+            );
+        assert_eq!(
+            graph.to_string(),
+            "[0:v:0][1:v:0]concat=n=2:v=1:a=0[v];\
+             [v]select=between(t\\,1.000\\,2.000),setpts=N/FRAME_RATE/TB,showinfo[out]"
+        );
+    }
+}
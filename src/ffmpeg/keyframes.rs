@@ -0,0 +1,154 @@
+use std::{fs::File, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use super::{process::job_name, scheduler::scheduler, VideoTimestamp};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Keyframe {
+    pub pts: VideoTimestamp,
+    /// Byte offset of the packet in the file, if the container reports one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pos: Option<u64>,
+}
+
+/// Keyframes of the first video stream, sorted by presentation time.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct KeyframeIndex {
+    pub keyframes: Vec<Keyframe>,
+}
+
+impl KeyframeIndex {
+    pub fn new(keyframes: Vec<Keyframe>) -> Self {
+        let mut keyframes = keyframes;
+        keyframes.sort_by_key(|k| k.pts);
+        keyframes.dedup_by_key(|k| k.pts);
+        Self { keyframes }
+    }
+
+    /// Reads the keyframes from the packet list of `input`, which demuxes but doesn't decode
+    /// the whole file.
+    pub fn probe(input: &str) -> anyhow::Result<Self> {
+        let mut cmd = scheduler().ffprobe();
+        cmd.arg("-v").arg("error");
+        cmd.arg("-select_streams").arg("v:0");
+        cmd.arg("-show_entries").arg("packet=pts_time,pos,flags");
+        cmd.arg("-of").arg("compact=p=0");
+        cmd.arg(input);
+
+        let stdout = scheduler().query(&mut cmd, &job_name("packets", input))?;
+        Ok(Self::new(
+            String::from_utf8_lossy(&stdout)
+                .lines()
+                .filter_map(parse_packet_line)
+                .collect(),
+        ))
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        Ok(serde_json::from_reader(File::open(path)?)?)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> anyhow::Result<()> {
+        if let Some(parent) = path.as_ref().parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        serde_json::to_writer(File::create(path)?, self)?;
+        Ok(())
+    }
+
+    /// Loads the index cached at `cache`, probing `input` and caching the result if there is
+    /// none.
+    pub fn load_or_probe<P: AsRef<Path>>(input: &str, cache: P) -> anyhow::Result<Self> {
+        let cache = cache.as_ref();
+        if cache.exists() {
+            return Self::load(cache);
+        }
+        let index = Self::probe(input)?;
+        if !scheduler().dry_run() {
+            index.save(cache)?;
+        }
+        Ok(index)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keyframes.is_empty()
+    }
+
+    /// The last keyframe at or before `timestamp`, where decoding must start to reach it.
+    pub fn at_or_before(&self, timestamp: &VideoTimestamp) -> Option<&Keyframe> {
+        let i = self.keyframes.partition_point(|k| k.pts <= *timestamp);
+        i.checked_sub(1).map(|i| &self.keyframes[i])
+    }
+}
+
+/// Parses a `pts_time=1.001000|pos=4096|flags=K__` line, keeping only keyframes.
+fn parse_packet_line(line: &str) -> Option<Keyframe> {
+    let mut pts = None;
+    let mut pos = None;
+    let mut keyframe = false;
+    for field in line.trim().split('|') {
+        match field.split_once('=')? {
+            ("pts_time", value) => pts = value.parse::<f64>().ok(),
+            ("pos", value) => pos = value.parse::<u64>().ok(),
+            ("flags", value) => keyframe = value.starts_with('K'),
+            _ => {}
+        }
+    }
+    if !keyframe {
+        return None;
+    }
+    Some(Keyframe {
+        pts: VideoTimestamp::from_float_seconds(pts?),
+        pos,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_packets() {
+        let index = KeyframeIndex::new(
+            [
+                "pts_time=2.002000|pos=90112|flags=K__",
+                "pts_time=0.033367|pos=5120|flags=___",
+                "pts_time=0.000000|pos=48|flags=K__",
+                "pts_time=N/A|pos=N/A|flags=K_D",
+                "pts_time=4.004000|pos=N/A|flags=K__",
+            ]
+            .into_iter()
+            .filter_map(parse_packet_line)
+            .collect(),
+        );
+        assert_eq!(
+            index.keyframes,
+            vec![
+                Keyframe {
+                    pts: VideoTimestamp::zero(),
+                    pos: Some(48)
+                },
+                Keyframe {
+                    pts: VideoTimestamp::from_millis(2002),
+                    pos: Some(90112)
+                },
+                Keyframe {
+                    pts: VideoTimestamp::from_millis(4004),
+                    pos: None
+                },
+            ]
+        );
+        let before = |ms| {
+            index
+                .at_or_before(&VideoTimestamp::from_millis(ms))
+                .map(|k| k.pts)
+        };
+        assert_eq!(before(2001), Some(VideoTimestamp::zero()));
+        assert_eq!(before(2002), Some(VideoTimestamp::from_millis(2002)));
+        assert_eq!(before(9000), Some(VideoTimestamp::from_millis(4004)));
+        assert!(KeyframeIndex::default()
+            .at_or_before(&VideoTimestamp::zero())
+            .is_none());
+    }
+}
@@ -1,10 +1,72 @@
-use std::{collections::HashMap, process::Command};
+use std::collections::HashMap;
 
 use serde::Deserialize;
 
+use super::{process::job_name, scheduler::scheduler, VideoTimestamp};
+
 #[derive(Debug, Deserialize)]
 pub struct StreamInfo {
     pub format: StreamFormat,
+    #[serde(default)]
+    pub streams: Vec<StreamDetail>,
+}
+
+impl StreamInfo {
+    pub fn video_stream(&self) -> Option<&StreamDetail> {
+        self.streams.iter().find(|s| s.codec_type == "video")
+    }
+
+    pub fn video_frame_rate(&self) -> Option<(u64, u64)> {
+        self.video_stream().and_then(|s| s.frame_rate())
+    }
+
+    /// Duration of the container, or of the video stream where the container reports `N/A`.
+    pub fn duration(&self) -> anyhow::Result<VideoTimestamp> {
+        self.format
+            .duration
+            .parse()
+            .or_else(|e| {
+                self.video_stream()
+                    .and_then(|s| s.duration.as_ref()?.parse().ok())
+                    .ok_or(e)
+            })
+            .map_err(|e: anyhow::Error| {
+                e.context(format!("No duration for {}", self.format.filename))
+            })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StreamDetail {
+    pub index: u64,
+    pub codec_type: String,
+    pub codec_name: Option<String>,
+    pub width: Option<u64>,
+    pub height: Option<u64>,
+    pub r_frame_rate: Option<String>,
+    pub avg_frame_rate: Option<String>,
+    pub duration: Option<String>,
+}
+
+impl StreamDetail {
+    /// Frame rate as a `(numerator, denominator)` pair, preferring the average rate ffprobe
+    /// measured over the nominal `r_frame_rate`.
+    pub fn frame_rate(&self) -> Option<(u64, u64)> {
+        [&self.avg_frame_rate, &self.r_frame_rate]
+            .into_iter()
+            .flatten()
+            .find_map(|rate| parse_rational(rate))
+    }
+}
+
+fn parse_rational(s: &str) -> Option<(u64, u64)> {
+    let (num, den) = s.split_once('/').unwrap_or((s, "1"));
+    let num = num.parse::<u64>().ok()?;
+    let den = den.parse::<u64>().ok()?;
+    if num == 0 || den == 0 {
+        return None;
+    }
+    Some((num, den))
 }
 
 #[derive(Debug, Deserialize)]
@@ -23,18 +85,50 @@ pub struct StreamFormat {
 }
 
 pub fn probe_format(input: &str) -> anyhow::Result<StreamInfo> {
-    let mut cmd = Command::new("ffprobe");
-    cmd.arg("-v").arg("quiet");
+    let mut cmd = scheduler().ffprobe();
+    cmd.arg("-v").arg("error");
     cmd.arg("-print_format").arg("json");
     cmd.arg("-show_format");
+    cmd.arg("-show_streams");
     cmd.arg(input);
 
-    let output = cmd.output()?;
-    if !output.status.success() {
-        return Err(anyhow::anyhow!("Failed to probe format"));
-    }
+    let stdout = scheduler().query(&mut cmd, &job_name("probe", input))?;
 
-    let info: StreamInfo = serde_json::from_slice(&output.stdout)?;
+    let info: StreamInfo = serde_json::from_slice(&stdout)?;
 
     Ok(info)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ffmpeg::scheduler::test_replay, runner::Recording};
+
+    #[test]
+    fn test_probe_duration_fallback() {
+        let json = r#"{
+            "streams": [
+                {"index": 0, "codec_type": "audio", "duration": "9.000000"},
+                {"index": 1, "codec_type": "video", "avg_frame_rate": "30000/1001",
+                 "duration": "12.345000"}
+            ],
+            "format": {
+                "filename": "live.mkv", "nb_streams": 2, "nb_programs": 0,
+                "format_name": "matroska,webm", "format_long_name": "Matroska / WebM",
+                "start_time": "0.000000", "duration": "N/A", "size": "1024",
+                "bit_rate": "N/A", "probe_score": 100, "tags": {}
+            }
+        }"#;
+        test_replay().push(Recording::success(
+            "ffprobe -v error -print_format json -show_format -show_streams live.mkv",
+            &json.lines().collect::<Vec<_>>(),
+        ));
+        let info = probe_format("live.mkv").unwrap();
+        assert_eq!(info.video_frame_rate(), Some((30000, 1001)));
+        assert_eq!(info.duration().unwrap(), VideoTimestamp::from_millis(12345));
+
+        let mut info = info;
+        info.streams.clear();
+        assert!(info.duration().is_err());
+    }
+}
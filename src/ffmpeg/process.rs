@@ -0,0 +1,303 @@
+use std::{
+    fmt,
+    io::Read,
+    path::{Path, PathBuf},
+    process::{Child, Command, ExitStatus},
+    time::{Duration, Instant},
+};
+
+use super::scheduler::scheduler;
+use crate::cancel::CancelToken;
+
+/// Lines of stderr kept in a [`ProcessError`].
+pub const STDERR_TAIL_LINES: usize = 20;
+
+const WAIT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A process that exited unsuccessfully, with what is needed to reproduce and diagnose it.
+#[derive(Debug)]
+pub struct ProcessError {
+    pub command: String,
+    pub status: ExitStatus,
+    pub stderr_tail: Vec<String>,
+    /// Where the complete stderr was written, if it was.
+    pub log: Option<PathBuf>,
+}
+
+impl fmt::Display for ProcessError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.command, self.status)?;
+        for line in &self.stderr_tail {
+            write!(f, "\n  {}", line)?;
+        }
+        if let Some(ref log) = self.log {
+            write!(f, "\n  full log: {}", log.display())?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ProcessError {}
+
+/// A command recorded in a dry run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlannedCommand {
+    pub command: String,
+    /// Run anyway, since it only reads and later commands depend on its output.
+    pub query: bool,
+}
+
+/// A POSIX shell script running `commands` in order until one fails. Queries are left as
+/// comments, as nothing in the script reads their output.
+pub fn shell_script(commands: &[PlannedCommand]) -> String {
+    let mut script = String::from("#!/bin/sh\nset -e\n");
+    for planned in commands {
+        if planned.query {
+            script.push_str("# ");
+        }
+        script.push_str(&planned.command);
+        script.push('\n');
+    }
+    script
+}
+
+/// The command as it could be pasted into a POSIX shell.
+pub fn command_line(cmd: &Command) -> String {
+    std::iter::once(cmd.get_program())
+        .chain(cmd.get_args())
+        .map(|arg| shell_quote(&arg.to_string_lossy()))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// A command writing `contents` to `path`, given inline as a here-document.
+pub fn heredoc<P: AsRef<Path>>(path: P, contents: &str) -> String {
+    let mut delimiter = String::from("EOF");
+    while contents.lines().any(|line| line == delimiter) {
+        delimiter.push('_');
+    }
+    let newline = if contents.is_empty() || contents.ends_with('\n') {
+        ""
+    } else {
+        "\n"
+    };
+    format!(
+        "cat > {} <<'{}'\n{}{}{}",
+        shell_quote(&path.as_ref().to_string_lossy()),
+        delimiter,
+        contents,
+        newline,
+        delimiter
+    )
+}
+
+pub(super) fn shell_quote(arg: &str) -> String {
+    let safe = |c: char| c.is_ascii_alphanumeric() || "-_./:=,+%@".contains(c);
+    if !arg.is_empty() && arg.chars().all(safe) {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', r"'\''"))
+    }
+}
+
+/// A short name for the logs of a process working on `path`, e.g. `probe-dQw4w9WgXcQ`.
+pub fn job_name<P: AsRef<Path>>(kind: &str, path: P) -> String {
+    match path.as_ref().file_stem() {
+        Some(stem) => format!("{}-{}", kind, stem.to_string_lossy()),
+        None => kind.to_string(),
+    }
+}
+
+/// Why a process was killed before it exited by itself.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop {
+    Cancelled,
+    TimedOut(Duration),
+}
+
+/// Waits for `child` to exit, killing it once `cancel` is cancelled or after `timeout`.
+pub fn wait_child(
+    child: &mut Child,
+    cancel: &CancelToken,
+    timeout: Option<Duration>,
+) -> std::io::Result<(ExitStatus, Option<Stop>)> {
+    let started = Instant::now();
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok((status, None));
+        }
+        let stop = if cancel.is_cancelled() {
+            Some(Stop::Cancelled)
+        } else {
+            timeout
+                .filter(|timeout| started.elapsed() >= *timeout)
+                .map(Stop::TimedOut)
+        };
+        if let Some(stop) = stop {
+            // it may have exited in the meantime
+            let _ = child.kill();
+            return Ok((child.wait()?, Some(stop)));
+        }
+        std::thread::sleep(WAIT_POLL_INTERVAL);
+    }
+}
+
+/// A file written under a temporary name next to its destination, so an interrupted write
+/// never looks finished. It is renamed into place by [`commit`](Self::commit) and removed if
+/// dropped before that.
+#[derive(Debug)]
+pub struct PartialOutput {
+    path: Option<PathBuf>,
+    output: PathBuf,
+}
+
+impl PartialOutput {
+    /// Keeps the extension of `output`, so ffmpeg still picks the format from it.
+    pub fn new<P: AsRef<Path>>(output: P) -> std::io::Result<Self> {
+        let output = output.as_ref().to_path_buf();
+        let stem = output.file_stem().unwrap_or_default().to_string_lossy();
+        let name = match output.extension() {
+            Some(ext) => format!(".{}.partial.{}", stem, ext.to_string_lossy()),
+            None => format!(".{}.partial", stem),
+        };
+        let path = output.with_file_name(name);
+        // left behind by a run that was killed outright
+        match scheduler().remove_file(&path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+        Ok(Self {
+            path: Some(path),
+            output,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        self.path.as_deref().unwrap()
+    }
+
+    /// In a dry run the rename is only planned, after the command that would have written
+    /// the file.
+    pub fn commit(mut self) -> std::io::Result<()> {
+        let path = self.path.take().unwrap();
+        if scheduler().dry_run() {
+            scheduler().record(
+                Command::new("mv").arg("-f").arg(&path).arg(&self.output),
+                false,
+            );
+            return Ok(());
+        }
+        std::fs::rename(path, &self.output)
+    }
+}
+
+impl Drop for PartialOutput {
+    fn drop(&mut self) {
+        if let Some(ref path) = self.path {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+/// Calls `f` with every line of `reader`, treating the carriage returns ffmpeg uses to
+/// redraw its status line as line ends too.
+pub fn for_each_line<R: Read, F: FnMut(&str)>(reader: R, mut f: F) -> std::io::Result<()> {
+    let mut line = Vec::new();
+    for byte in std::io::BufReader::new(reader).bytes() {
+        match byte? {
+            b'\n' | b'\r' => {
+                if !line.is_empty() {
+                    f(&String::from_utf8_lossy(&line));
+                    line.clear();
+                }
+            }
+            b => line.push(b),
+        }
+    }
+    if !line.is_empty() {
+        f(&String::from_utf8_lossy(&line));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_command_line() {
+        let mut cmd = Command::new("ffmpeg");
+        cmd.arg("-i")
+            .arg("data/videos/a b.mkv")
+            .arg("-vf")
+            .arg("select='gte(t,1)'")
+            .arg("out.jpg");
+        assert_eq!(
+            command_line(&cmd),
+            r#"ffmpeg -i 'data/videos/a b.mkv' -vf 'select='\''gte(t,1)'\''' out.jpg"#
+        );
+        assert_eq!(job_name("probe", "data/videos/abc.mkv"), "probe-abc");
+
+        let planned = [
+            PlannedCommand {
+                command: "ffprobe in.mkv".into(),
+                query: true,
+            },
+            PlannedCommand {
+                command: command_line(&cmd),
+                query: false,
+            },
+        ];
+        assert_eq!(
+            shell_script(&planned),
+            format!(
+                "#!/bin/sh\nset -e\n# ffprobe in.mkv\n{}\n",
+                command_line(&cmd)
+            )
+        );
+    }
+
+    #[test]
+    fn test_partial_output() {
+        let dir = std::env::temp_dir().join(format!("partial-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let output = dir.join("clip.mkv");
+
+        let partial = PartialOutput::new(&output).unwrap();
+        assert_eq!(partial.path(), dir.join(".clip.partial.mkv"));
+        std::fs::write(partial.path(), "half").unwrap();
+        drop(partial);
+        assert!(std::fs::read_dir(&dir).unwrap().next().is_none());
+
+        let partial = PartialOutput::new(&output).unwrap();
+        std::fs::write(partial.path(), "done").unwrap();
+        partial.commit().unwrap();
+        assert_eq!(std::fs::read_to_string(&output).unwrap(), "done");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_wait_child_stops() {
+        let cancel = CancelToken::new();
+        let mut child = Command::new("sleep").arg("10").spawn().unwrap();
+        let (status, stop) =
+            wait_child(&mut child, &cancel, Some(Duration::from_millis(200))).unwrap();
+        assert!(!status.success());
+        assert_eq!(stop, Some(Stop::TimedOut(Duration::from_millis(200))));
+
+        let mut child = Command::new("sleep").arg("10").spawn().unwrap();
+        cancel.cancel();
+        let (_, stop) = wait_child(&mut child, &cancel, None).unwrap();
+        assert_eq!(stop, Some(Stop::Cancelled));
+    }
+
+    #[test]
+    fn test_for_each_line() {
+        let mut lines = Vec::new();
+        for_each_line(&b"a\nframe=1\rframe=2\r\nb"[..], |l| {
+            lines.push(l.to_string())
+        })
+        .unwrap();
+        assert_eq!(lines, ["a", "frame=1", "frame=2", "b"]);
+    }
+}
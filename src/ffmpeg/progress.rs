@@ -0,0 +1,133 @@
+use std::{process::Command, time::Duration};
+
+use super::{scheduler::scheduler, VideoTimestamp};
+
+/// One report from ffmpeg's `-progress` output.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Progress {
+    /// Position written so far in the output.
+    pub out_time: VideoTimestamp,
+    pub fps: Option<f64>,
+    /// Multiple of real time, e.g. `2.0` when encoding twice as fast as playback.
+    pub speed: Option<f64>,
+    /// Set on the last report.
+    pub finished: bool,
+}
+
+impl Progress {
+    /// Part of `total` done, from 0 to 1.
+    pub fn fraction(&self, total: VideoTimestamp) -> f64 {
+        if total.as_micros() == 0 {
+            return if self.finished { 1.0 } else { 0.0 };
+        }
+        (self.out_time.as_float_seconds() / total.as_float_seconds()).clamp(0.0, 1.0)
+    }
+
+    /// Wall time left at the current speed.
+    pub fn eta(&self, total: VideoTimestamp) -> Option<Duration> {
+        let speed = self.speed.filter(|speed| *speed > 0.0)?;
+        let left = total.checked_sub(&self.out_time).unwrap_or_default();
+        Some(Duration::from_secs_f64(left.as_secs_f64() / speed))
+    }
+
+    /// The same report for an output that starts `offset` into a larger whole.
+    pub fn offset(&self, offset: VideoTimestamp) -> Self {
+        Self {
+            out_time: self.out_time + offset,
+            ..*self
+        }
+    }
+}
+
+/// Collects the `key=value` lines of a `-progress` stream into a [`Progress`] for every
+/// block, which ffmpeg ends with `progress=continue` or `progress=end`.
+#[derive(Debug, Default)]
+pub struct ProgressParser {
+    current: Progress,
+}
+
+impl ProgressParser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push_line(&mut self, line: &str) -> Option<Progress> {
+        let (key, value) = line.split_once('=')?;
+        let value = value.trim();
+        match key.trim() {
+            // despite the name, out_time_ms is in microseconds as well
+            "out_time_us" | "out_time_ms" => {
+                if let Ok(micros) = value.parse::<i64>() {
+                    self.current.out_time = VideoTimestamp::from_micros(micros.max(0) as u64);
+                }
+            }
+            "fps" => self.current.fps = value.parse().ok(),
+            "speed" => self.current.speed = value.trim_end_matches('x').parse().ok(),
+            "progress" => {
+                self.current.finished = value == "end";
+                return Some(self.current);
+            }
+            _ => {}
+        }
+        None
+    }
+}
+
+/// Adds the options that make ffmpeg report progress on stdout instead of its status line.
+pub fn progress_args(cmd: &mut Command) {
+    cmd.arg("-nostats");
+    cmd.arg("-progress").arg("pipe:1");
+}
+
+/// Runs an ffmpeg command set up with [`progress_args`], passing every progress report to
+/// `on_progress`. Other output is logged at debug level.
+pub fn run_with_progress<P>(cmd: &mut Command, job: &str, mut on_progress: P) -> anyhow::Result<()>
+where
+    P: FnMut(&Progress) + Send,
+{
+    let mut parser = ProgressParser::new();
+    scheduler().run_lines(
+        cmd,
+        job,
+        |line| {
+            if let Some(progress) = parser.push_line(line) {
+                on_progress(&progress);
+            }
+        },
+        |line| log::debug!("{}", line),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_progress() {
+        let output = "frame=120\nfps=29.97\nbitrate=N/A\nout_time_us=4000000\n\
+                      out_time_ms=4000000\nout_time=00:00:04.000000\nspeed=2.00x\n\
+                      progress=continue\nframe=240\nfps=30.1\nout_time_ms=8000000\n\
+                      speed=N/A\nprogress=end\n";
+        let mut parser = ProgressParser::new();
+        let reports = output
+            .lines()
+            .filter_map(|line| parser.push_line(line))
+            .collect::<Vec<_>>();
+        assert_eq!(reports.len(), 2);
+        assert_eq!(reports[0].out_time, VideoTimestamp::from_seconds(4));
+        assert_eq!(reports[0].fps, Some(29.97));
+        assert_eq!(reports[0].speed, Some(2.0));
+        assert!(!reports[0].finished);
+        assert_eq!(reports[1].speed, None);
+        assert!(reports[1].finished);
+
+        let total = VideoTimestamp::from_seconds(10);
+        assert_eq!(reports[0].fraction(total), 0.4);
+        assert_eq!(reports[0].eta(total), Some(Duration::from_secs(3)));
+        assert_eq!(reports[1].eta(total), None);
+        assert_eq!(
+            reports[0].offset(VideoTimestamp::from_seconds(1)).out_time,
+            VideoTimestamp::from_seconds(5)
+        );
+    }
+}
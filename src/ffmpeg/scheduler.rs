@@ -0,0 +1,492 @@
+use std::{
+    collections::VecDeque,
+    io::Write,
+    path::{Path, PathBuf},
+    process::Command,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Condvar, Mutex, OnceLock,
+    },
+    time::Duration,
+};
+
+use serde::Serialize;
+
+use super::process::{
+    command_line, heredoc, shell_quote, PlannedCommand, ProcessError, Stop, STDERR_TAIL_LINES,
+};
+#[cfg(test)]
+use crate::runner::ReplayRunner;
+use crate::{
+    cancel::{CancelToken, Cancelled},
+    runner::{CommandRunner, SystemRunner},
+};
+
+/// Limits how many ffmpeg and ffprobe processes run at once across the whole program, and
+/// how many threads each of them may use.
+pub struct ProcessScheduler {
+    max_processes: usize,
+    threads: Option<usize>,
+    log_dir: Option<PathBuf>,
+    log_successful: bool,
+    timeout: Option<Duration>,
+    cancel: CancelToken,
+    dry_run: bool,
+    plan: Mutex<Vec<PlannedCommand>>,
+    runner: Arc<dyn CommandRunner>,
+    state: Mutex<SchedulerStats>,
+    released: Condvar,
+    log_seq: AtomicUsize,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct SchedulerConfig {
+    /// One per core if unset.
+    pub max_processes: Option<usize>,
+    pub threads: Option<usize>,
+    /// Where the stderr of failed processes is written, one file per process.
+    pub log_dir: Option<PathBuf>,
+    /// Write logs of processes that succeeded too.
+    pub log_successful: bool,
+    /// Longest a single process may run before it is killed.
+    pub timeout: Option<Duration>,
+    /// Kills running processes and refuses to start new ones once cancelled.
+    pub cancel: CancelToken,
+    /// Record commands instead of running them, apart from queries.
+    pub dry_run: bool,
+    /// Runs the processes, [`SystemRunner`] if unset.
+    pub runner: Option<Arc<dyn CommandRunner>>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct SchedulerStats {
+    pub running: usize,
+    pub queued: usize,
+    pub completed: usize,
+}
+
+/// A running slot, released when dropped.
+pub struct ProcessPermit<'a> {
+    scheduler: &'a ProcessScheduler,
+}
+
+static SCHEDULER: OnceLock<ProcessScheduler> = OnceLock::new();
+
+fn cores() -> usize {
+    std::thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+/// Sets up the scheduler used by every ffmpeg invocation. Fails if it was already used.
+pub fn configure_scheduler(config: SchedulerConfig) -> anyhow::Result<()> {
+    SCHEDULER
+        .set(ProcessScheduler::new(config))
+        .map_err(|_| anyhow::anyhow!("ffmpeg scheduler is already in use"))
+}
+
+/// The global scheduler, allowing one process per core and keeping no logs unless configured
+/// otherwise.
+pub fn scheduler() -> &'static ProcessScheduler {
+    SCHEDULER.get_or_init(|| {
+        let config = SchedulerConfig::default();
+        // unit tests never run real programs through the global scheduler
+        #[cfg(test)]
+        let config = SchedulerConfig {
+            runner: Some(test_replay().clone()),
+            ..config
+        };
+        ProcessScheduler::new(config)
+    })
+}
+
+/// The recordings the global scheduler plays back in unit tests.
+#[cfg(test)]
+pub(crate) fn test_replay() -> &'static Arc<ReplayRunner> {
+    static REPLAY: OnceLock<Arc<ReplayRunner>> = OnceLock::new();
+    REPLAY.get_or_init(|| Arc::new(ReplayRunner::new()))
+}
+
+impl ProcessScheduler {
+    pub fn new(config: SchedulerConfig) -> Self {
+        Self {
+            max_processes: config.max_processes.unwrap_or_else(cores).max(1),
+            threads: config.threads,
+            log_dir: config.log_dir,
+            log_successful: config.log_successful,
+            timeout: config.timeout,
+            cancel: config.cancel,
+            dry_run: config.dry_run,
+            plan: Mutex::new(Vec::new()),
+            runner: config.runner.unwrap_or_else(|| Arc::new(SystemRunner)),
+            state: Mutex::new(SchedulerStats::default()),
+            released: Condvar::new(),
+            log_seq: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn max_processes(&self) -> usize {
+        self.max_processes
+    }
+
+    pub fn cancel_token(&self) -> &CancelToken {
+        &self.cancel
+    }
+
+    pub fn dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    /// What runs the processes. Commands not limited by the scheduler, such as downloads, are
+    /// run with it directly.
+    pub fn runner(&self) -> &dyn CommandRunner {
+        self.runner.as_ref()
+    }
+
+    /// Adds `cmd` to the plan of a dry run. Commands not run through the scheduler, such as
+    /// downloads, record themselves with this.
+    pub fn record(&self, cmd: &Command, query: bool) {
+        self.plan(command_line(cmd), query);
+    }
+
+    /// Like [`record`](Self::record), with the stderr of `cmd` going to the file `stderr`.
+    pub fn record_with_stderr<P: AsRef<Path>>(&self, cmd: &Command, stderr: P) {
+        let stderr = shell_quote(&stderr.as_ref().to_string_lossy());
+        self.plan(format!("{} 2> {}", command_line(cmd), stderr), false);
+    }
+
+    fn plan(&self, command: String, query: bool) {
+        log::info!(
+            "{}{}",
+            if query { "query: " } else { "would run: " },
+            command
+        );
+        self.plan
+            .lock()
+            .unwrap()
+            .push(PlannedCommand { command, query });
+    }
+
+    /// Creates `dir`, or in a dry run plans to, so planned commands writing into it work.
+    pub fn create_dir_all<P: AsRef<Path>>(&self, dir: P) -> std::io::Result<()> {
+        if self.dry_run {
+            self.record(Command::new("mkdir").arg("-p").arg(dir.as_ref()), false);
+            return Ok(());
+        }
+        std::fs::create_dir_all(dir)
+    }
+
+    pub fn remove_dir_all<P: AsRef<Path>>(&self, dir: P) -> std::io::Result<()> {
+        if self.dry_run {
+            self.record(Command::new("rm").arg("-rf").arg(dir.as_ref()), false);
+            return Ok(());
+        }
+        std::fs::remove_dir_all(dir)
+    }
+
+    /// Writes `contents` to `path`, or in a dry run plans to, so the script carries the files
+    /// its commands read instead of leaving them on this machine.
+    pub fn write_file<P: AsRef<Path>>(&self, path: P, contents: &str) -> std::io::Result<()> {
+        if self.dry_run {
+            self.plan(heredoc(path, contents), false);
+            return Ok(());
+        }
+        std::fs::write(path, contents)
+    }
+
+    pub fn remove_file<P: AsRef<Path>>(&self, path: P) -> std::io::Result<()> {
+        if self.dry_run {
+            self.record(Command::new("rm").arg("-f").arg(path.as_ref()), false);
+            return Ok(());
+        }
+        std::fs::remove_file(path)
+    }
+
+    /// Every command recorded so far, in order.
+    pub fn planned(&self) -> Vec<PlannedCommand> {
+        self.plan.lock().unwrap().clone()
+    }
+
+    /// An `ffmpeg` command with the configured decoder thread count.
+    pub fn ffmpeg(&self) -> Command {
+        let mut cmd = Command::new("ffmpeg");
+        if let Some(threads) = self.threads {
+            cmd.arg("-threads").arg(threads.to_string());
+        }
+        cmd
+    }
+
+    pub fn ffprobe(&self) -> Command {
+        Command::new("ffprobe")
+    }
+
+    /// Blocks until fewer than `max_processes` processes are running.
+    pub fn acquire(&self) -> ProcessPermit<'_> {
+        let mut state = self.state.lock().unwrap();
+        state.queued += 1;
+        while state.running >= self.max_processes {
+            state = self.released.wait(state).unwrap();
+        }
+        state.queued -= 1;
+        state.running += 1;
+        ProcessPermit { scheduler: self }
+    }
+
+    /// Runs `cmd` to completion once a slot is free and returns its stdout. Every stderr line
+    /// is passed to `on_stderr` and kept for the log of `job`; failures become a
+    /// [`ProcessError`].
+    pub fn run<F: FnMut(&str)>(
+        &self,
+        cmd: &mut Command,
+        job: &str,
+        on_stderr: F,
+    ) -> anyhow::Result<Vec<u8>> {
+        let mut stdout = Vec::new();
+        self.run_lines(
+            cmd,
+            job,
+            |line| {
+                stdout.extend_from_slice(line.as_bytes());
+                stdout.push(b'\n');
+            },
+            on_stderr,
+        )?;
+        Ok(stdout)
+    }
+
+    /// Like [`run`](Self::run), but passes stdout line by line to `on_stdout` while the
+    /// process runs. Fails with [`Cancelled`] if the process was killed by the cancel token.
+    /// In a dry run `cmd` is only recorded, producing no output.
+    pub fn run_lines<O, E>(
+        &self,
+        cmd: &mut Command,
+        job: &str,
+        on_stdout: O,
+        on_stderr: E,
+    ) -> anyhow::Result<()>
+    where
+        O: FnMut(&str) + Send,
+        E: FnMut(&str),
+    {
+        if self.dry_run {
+            self.record(cmd, false);
+            return Ok(());
+        }
+        self.execute(cmd, job, on_stdout, on_stderr)
+    }
+
+    /// Like [`run`](Self::run) for commands without side effects, such as probes, which run
+    /// even in a dry run since what comes next depends on their output.
+    pub fn query(&self, cmd: &mut Command, job: &str) -> anyhow::Result<Vec<u8>> {
+        if self.dry_run {
+            self.record(cmd, true);
+        }
+        let mut stdout = Vec::new();
+        self.execute(
+            cmd,
+            job,
+            |line| {
+                stdout.extend_from_slice(line.as_bytes());
+                stdout.push(b'\n');
+            },
+            |_| {},
+        )?;
+        Ok(stdout)
+    }
+
+    fn execute<O, E>(
+        &self,
+        cmd: &mut Command,
+        job: &str,
+        mut on_stdout: O,
+        mut on_stderr: E,
+    ) -> anyhow::Result<()>
+    where
+        O: FnMut(&str) + Send,
+        E: FnMut(&str),
+    {
+        let command = command_line(cmd);
+        let _permit = self.acquire();
+        self.cancel.check()?;
+        let mut stderr = Vec::new();
+        let outcome = self.runner.run(
+            cmd,
+            &mut on_stdout,
+            &mut |line| {
+                on_stderr(line);
+                stderr.push(line.to_string());
+            },
+            &self.cancel,
+            self.timeout,
+        )?;
+        let (status, stop) = (outcome.status, outcome.stop);
+        if stop == Some(Stop::Cancelled) {
+            return Err(Cancelled.into());
+        }
+
+        let log = if !status.success() || self.log_successful {
+            self.write_log(job, &command, &stderr)
+                .map_err(|e| log::warn!("Failed to write log of {}: {}", job, e))
+                .ok()
+                .flatten()
+        } else {
+            None
+        };
+        if !status.success() {
+            let tail = stderr
+                .into_iter()
+                .rev()
+                .take(STDERR_TAIL_LINES)
+                .collect::<VecDeque<_>>();
+            let error = anyhow::Error::from(ProcessError {
+                command,
+                status,
+                stderr_tail: tail.into_iter().rev().collect(),
+                log,
+            });
+            return Err(match stop {
+                Some(Stop::TimedOut(timeout)) => {
+                    error.context(format!("Timed out after {}s", timeout.as_secs()))
+                }
+                _ => error,
+            });
+        }
+        Ok(())
+    }
+
+    fn write_log(
+        &self,
+        job: &str,
+        command: &str,
+        stderr: &[String],
+    ) -> std::io::Result<Option<PathBuf>> {
+        let Some(ref dir) = self.log_dir else {
+            return Ok(None);
+        };
+        std::fs::create_dir_all(dir)?;
+        let path = dir.join(format!(
+            "{}-{}-{}.log",
+            chrono::Utc::now().format("%Y%m%dT%H%M%S"),
+            self.log_seq.fetch_add(1, Ordering::Relaxed),
+            job
+        ));
+        let mut file = std::fs::File::create(&path)?;
+        writeln!(file, "$ {}", command)?;
+        for line in stderr {
+            writeln!(file, "{}", line)?;
+        }
+        Ok(Some(path))
+    }
+
+    pub fn stats(&self) -> SchedulerStats {
+        *self.state.lock().unwrap()
+    }
+}
+
+impl Drop for ProcessPermit<'_> {
+    fn drop(&mut self) {
+        let mut state = self.scheduler.state.lock().unwrap();
+        state.running -= 1;
+        state.completed += 1;
+        self.scheduler.released.notify_one();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::ffmpeg::process::shell_script;
+
+    #[test]
+    fn test_scheduler_limits_processes() {
+        let scheduler = ProcessScheduler::new(SchedulerConfig {
+            max_processes: Some(2),
+            threads: Some(3),
+            ..SchedulerConfig::default()
+        });
+        let running = AtomicUsize::new(0);
+        let peak = AtomicUsize::new(0);
+        std::thread::scope(|s| {
+            for _ in 0..8 {
+                s.spawn(|| {
+                    let _permit = scheduler.acquire();
+                    let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                    peak.fetch_max(now, Ordering::SeqCst);
+                    std::thread::sleep(std::time::Duration::from_millis(5));
+                    running.fetch_sub(1, Ordering::SeqCst);
+                });
+            }
+        });
+        assert!(peak.load(Ordering::SeqCst) <= 2);
+        assert_eq!(
+            scheduler.stats(),
+            SchedulerStats {
+                running: 0,
+                queued: 0,
+                completed: 8
+            }
+        );
+        let cmd = scheduler.ffmpeg();
+        assert_eq!(cmd.get_args().collect::<Vec<_>>(), ["-threads", "3"]);
+    }
+
+    #[test]
+    fn test_run_keeps_stderr() {
+        let log_dir = std::env::temp_dir().join(format!("scheduler-test-{}", std::process::id()));
+        let scheduler = ProcessScheduler::new(SchedulerConfig {
+            log_dir: Some(log_dir.clone()),
+            ..SchedulerConfig::default()
+        });
+
+        let mut lines = Vec::new();
+        let stdout = scheduler
+            .run(
+                Command::new("sh").arg("-c").arg("echo out; echo err >&2"),
+                "ok",
+                |line| lines.push(line.to_string()),
+            )
+            .unwrap();
+        assert_eq!(stdout, b"out\n");
+        assert_eq!(lines, ["err"]);
+        assert!(!log_dir.exists());
+
+        let err = scheduler
+            .run(
+                Command::new("sh")
+                    .arg("-c")
+                    .arg("for i in $(seq 30); do echo line$i >&2; done; exit 3"),
+                "fail",
+                |_| {},
+            )
+            .unwrap_err();
+        let err = err.downcast::<ProcessError>().unwrap();
+        assert_eq!(err.status.code(), Some(3));
+        assert_eq!(err.stderr_tail.len(), STDERR_TAIL_LINES);
+        assert_eq!(err.stderr_tail.last().unwrap(), "line30");
+        let log = std::fs::read_to_string(err.log.unwrap()).unwrap();
+        assert!(log.starts_with("$ sh -c "));
+        assert_eq!(log.lines().count(), 31);
+        std::fs::remove_dir_all(log_dir).unwrap();
+    }
+
+    #[test]
+    fn test_dry_run_plans_files() {
+        let dir = std::env::temp_dir().join(format!("dry-run-test-{}", std::process::id()));
+        let list = dir.join("it's a list.txt");
+        let scheduler = ProcessScheduler::new(SchedulerConfig {
+            dry_run: true,
+            ..SchedulerConfig::default()
+        });
+        scheduler.create_dir_all(&dir).unwrap();
+        let contents = "file 'a.mkv'\nEOF\n$HOME `b` \\\n";
+        scheduler.write_file(&list, contents).unwrap();
+        scheduler.remove_file(dir.join("stale.txt")).unwrap();
+        assert!(!dir.exists());
+
+        let script = shell_script(&scheduler.planned());
+        let status = Command::new("sh").arg("-c").arg(&script).status().unwrap();
+        assert!(status.success());
+        assert_eq!(std::fs::read_to_string(&list).unwrap(), contents);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,19 +1,107 @@
-use std::{path::PathBuf, process::Command};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    process::Command,
+    sync::OnceLock,
+};
 
-use super::VideoTimestamp;
+use log::debug;
+use regex::Regex;
 
-#[derive(Debug)]
+use super::{
+    filter::{Chain, Filter},
+    keyframes::{Keyframe, KeyframeIndex},
+    process::job_name,
+    scheduler::scheduler,
+    VideoTimestamp,
+};
+
+const FRAMES_INDEX_FILE: &str = "frames.json";
+/// Where a planned extraction logs what ffmpeg reports, for a later run to index its thumbnails.
+const SHOWINFO_LOG_FILE: &str = "showinfo.log";
+const SHOWINFO_PATTERN: &str = r"\bn:\s*\d+\s+pts:\s*-?\d+\s+pts_time:(-?[\d.]+)";
+const SHOWINFO_POS_PATTERN: &str = r"\bpos:\s*(\d+)";
+
+#[derive(Debug, Clone)]
 pub struct Thumbnail {
     pub seq: u64,
     pub path: String,
     pub timestamp: VideoTimestamp,
 }
 
-pub fn collect_thumbnail_into(
-    output: PathBuf,
-    from: Option<VideoTimestamp>,
-    fps: (u64, u64),
-) -> anyhow::Result<Vec<Thumbnail>> {
+fn parse_showinfo_line(regex: &Regex, line: &str) -> Option<VideoTimestamp> {
+    if !line.contains("showinfo") {
+        return None;
+    }
+    let caps = regex.captures(line)?;
+    let pts_time = caps[1].parse::<f64>().ok()?;
+    Some(VideoTimestamp::from_float_seconds(pts_time.max(0.0)))
+}
+
+fn parse_showinfo_pos(regex: &Regex, line: &str) -> Option<u64> {
+    regex.captures(line)?[1].parse().ok()
+}
+
+/// The frame a `showinfo` line reports, if it reports one.
+fn parse_showinfo_frame(line: &str) -> Option<Keyframe> {
+    static REGEXES: OnceLock<(Regex, Regex)> = OnceLock::new();
+    let (regex, pos_regex) = REGEXES.get_or_init(|| {
+        (
+            Regex::new(SHOWINFO_PATTERN).unwrap(),
+            Regex::new(SHOWINFO_POS_PATTERN).unwrap(),
+        )
+    });
+    Some(Keyframe {
+        pts: parse_showinfo_line(regex, line)?,
+        pos: parse_showinfo_pos(pos_regex, line),
+    })
+}
+
+/// Timestamps of the thumbnails written for `frames`, by their sequence number.
+fn index_frames(frames: &[Keyframe]) -> HashMap<u64, VideoTimestamp> {
+    frames
+        .iter()
+        .enumerate()
+        .map(|(i, frame)| (i as u64 + 1, frame.pts))
+        .collect()
+}
+
+/// Runs an ffmpeg command whose filtergraph ends in `showinfo` and returns the presentation
+/// timestamp and byte position of every frame it reported, in output order. Other stderr
+/// lines are either passed through or logged at debug level.
+fn run_with_showinfo(
+    mut cmd: Command,
+    job: &str,
+    passthrough: bool,
+) -> anyhow::Result<Vec<Keyframe>> {
+    let mut frames = Vec::new();
+    scheduler().run(&mut cmd, job, |line| match parse_showinfo_frame(line) {
+        Some(frame) => frames.push(frame),
+        None if passthrough => eprintln!("{}", line),
+        None => debug!("{}", line),
+    })?;
+
+    Ok(frames)
+}
+
+pub fn collect_thumbnail_into(output: PathBuf, fps: (u64, u64)) -> anyhow::Result<Vec<Thumbnail>> {
+    let frames_index = output.join(FRAMES_INDEX_FILE);
+    let showinfo_log = output.join(SHOWINFO_LOG_FILE);
+    let frames_index = if frames_index.exists() {
+        Some(serde_json::from_reader::<_, HashMap<u64, VideoTimestamp>>(
+            std::fs::File::open(frames_index)?,
+        )?)
+    } else if showinfo_log.exists() {
+        let log = std::fs::read_to_string(showinfo_log)?;
+        Some(index_frames(
+            &log.lines()
+                .filter_map(parse_showinfo_frame)
+                .collect::<Vec<_>>(),
+        ))
+    } else {
+        None
+    };
+
     let mut thumbs = Vec::new();
 
     for entry in std::fs::read_dir(output)? {
@@ -31,12 +119,12 @@ pub fn collect_thumbnail_into(
                 .unwrap()
                 .parse::<u64>()
                 .unwrap();
-            let timestamp = VideoTimestamp::from_float_seconds(
-                from.as_ref()
-                    .map(|from| from.as_float_seconds())
-                    .unwrap_or(0.0)
-                    + (seq as f64 - 0.5) / (fps.0 as f64 / fps.1 as f64),
-            );
+            // thumbnails generated before PTS were recorded only have their sequence number
+            let timestamp = match frames_index.as_ref().and_then(|index| index.get(&seq)) {
+                Some(timestamp) => *timestamp,
+                // half a frame before the sequence number, in units of half frames
+                None => VideoTimestamp::from_frame((2 * seq).saturating_sub(1), (fps.0 * 2, fps.1)),
+            };
             thumbs.push(Thumbnail {
                 seq,
                 path: path.to_str().unwrap().into(),
@@ -48,31 +136,250 @@ pub fn collect_thumbnail_into(
     Ok(thumbs)
 }
 
+/// Starts reading `input` at the last keyframe at or before `at`, so nothing before it is
+/// decoded. Timestamps are kept as they are in the file, so filters and `showinfo` still see
+/// the same times as when decoding from the start.
+fn seek_input(cmd: &mut Command, at: &VideoTimestamp, keyframes: &KeyframeIndex) {
+    cmd.arg("-copyts");
+    if let Some(keyframe) = keyframes.at_or_before(at) {
+        // the keyframe itself is the seek target, exact seeking would only add a decode pass
+        cmd.arg("-noaccurate_seek");
+        cmd.arg("-ss")
+            .arg(format!("{:.6}", keyframe.pts.as_float_seconds()));
+    }
+}
+
 pub fn generate_thumbnails(
     input: &str,
     output: PathBuf,
-    from: Option<VideoTimestamp>,
-    to: Option<VideoTimestamp>,
     fps: (u64, u64),
 ) -> anyhow::Result<Vec<Thumbnail>> {
-    let mut cmd = Command::new("ffmpeg");
+    let mut cmd = scheduler().ffmpeg();
     cmd.arg("-i").arg(input);
-    if let Some(ref from) = from {
-        cmd.arg("-ss").arg(from.as_ffmpeg_arg());
-    }
-    if let Some(ref to) = to {
-        cmd.arg("-to").arg(to.as_ffmpeg_arg());
+
+    // pick source frames instead of resampling with the fps filter, so every thumbnail keeps
+    // the presentation timestamp of the frame it was taken from
+    let vf = Chain::new()
+        .filter(Filter::new("select").arg(format!(
+            "isnan(prev_selected_t)+gte(t-prev_selected_t,{:.6})",
+            fps.1 as f64 / fps.0 as f64 - 0.001
+        )))
+        .filter(Filter::new("showinfo"));
+
+    cmd.arg("-vf").arg(vf.to_string());
+    cmd.arg("-vsync").arg("0");
+    cmd.arg("-qscale:v").arg("2");
+    cmd.arg("-f").arg("image2");
+    cmd.arg(output.join("thumb%04d.jpg"));
+
+    if scheduler().dry_run() {
+        scheduler().record_with_stderr(&cmd, output.join(SHOWINFO_LOG_FILE));
+        return Ok(Vec::new());
     }
-    cmd.arg("-vf").arg(format!("fps={}/{}", fps.0, fps.1));
+    let frames = run_with_showinfo(cmd, &job_name("thumbnails", input), true)
+        .map_err(|e| anyhow::anyhow!("Failed to generate thumbnails: {}", e))?;
+    serde_json::to_writer(
+        std::fs::File::create(output.join(FRAMES_INDEX_FILE))?,
+        &index_frames(&frames),
+    )?;
+
+    collect_thumbnail_into(output, fps)
+}
+
+/// Writes a thumbnail of every keyframe and nothing else, decoding with `-skip_frame nokey`.
+/// The frames seen along the way are returned as the keyframe index of the video.
+pub fn generate_keyframe_thumbnails(
+    input: &str,
+    output: PathBuf,
+) -> anyhow::Result<(Vec<Thumbnail>, KeyframeIndex)> {
+    let mut cmd = scheduler().ffmpeg();
+    cmd.arg("-skip_frame").arg("nokey");
+    cmd.arg("-i").arg(input);
+    cmd.arg("-vf")
+        .arg(Chain::new().filter(Filter::new("showinfo")).to_string());
     cmd.arg("-vsync").arg("0");
     cmd.arg("-qscale:v").arg("2");
     cmd.arg("-f").arg("image2");
     cmd.arg(output.join("thumb%04d.jpg"));
-    cmd.stderr(std::process::Stdio::inherit());
-    cmd.stdout(std::process::Stdio::inherit());
-    if !cmd.spawn()?.wait()?.success() {
-        return Err(anyhow::anyhow!("Failed to generate thumbnails"));
+
+    if scheduler().dry_run() {
+        scheduler().record_with_stderr(&cmd, output.join(SHOWINFO_LOG_FILE));
+        return Ok((Vec::new(), KeyframeIndex::default()));
     }
+    let keyframes = run_with_showinfo(cmd, &job_name("keyframes", input), true)
+        .map_err(|e| anyhow::anyhow!("Failed to generate keyframe thumbnails: {}", e))?;
+    serde_json::to_writer(
+        std::fs::File::create(output.join(FRAMES_INDEX_FILE))?,
+        &index_frames(&keyframes),
+    )?;
+
+    Ok((
+        collect_thumbnail_into(output, (1, 1))?,
+        KeyframeIndex::new(keyframes),
+    ))
+}
 
-    Ok(collect_thumbnail_into(output, from, fps)?)
+/// Extracts the first frame at or after `at` and returns its presentation timestamp.
+pub fn extract_frame(
+    input: &str,
+    at: &VideoTimestamp,
+    output: &Path,
+    keyframes: Option<&KeyframeIndex>,
+) -> anyhow::Result<VideoTimestamp> {
+    let mut cmd = scheduler().ffmpeg();
+    cmd.arg("-hide_banner").arg("-nostats");
+    let vf = match keyframes {
+        Some(keyframes) => {
+            seek_input(&mut cmd, at, keyframes);
+            Chain::new()
+                .filter(Filter::new("select").arg(format!("gte(t,{:.6})", at.as_float_seconds())))
+        }
+        None => {
+            cmd.arg("-copyts");
+            cmd.arg("-ss").arg(at.as_ffmpeg_arg());
+            Chain::new()
+        }
+    };
+    cmd.arg("-i").arg(input);
+    cmd.arg("-vf")
+        .arg(vf.filter(Filter::new("showinfo")).to_string());
+    cmd.arg("-frames:v").arg("1");
+    cmd.arg("-qscale:v").arg("2");
+    cmd.arg("-y");
+    cmd.arg(output);
+    let frames = run_with_showinfo(cmd, &job_name("frame", input), false)?;
+    match frames.into_iter().next() {
+        Some(frame) if output.exists() => Ok(frame.pts),
+        _ => Err(anyhow::anyhow!("Failed to extract frame")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        ffmpeg::{probe::probe_format, scheduler::test_replay},
+        recog::{image_file_mictest_score, MICTEST_SCORE_THRESHOLD},
+        runner::Recording,
+    };
+
+    #[test]
+    fn test_parse_showinfo_line() {
+        let regex = Regex::new(SHOWINFO_PATTERN).unwrap();
+        let line = "[Parsed_showinfo_1 @ 0x55d0c5a3c240] n:  12 pts:  49049 pts_time:1.63497 \
+                    duration:1001 duration_time:0.0333667 fmt:yuv420p";
+        assert_eq!(
+            parse_showinfo_line(&regex, line),
+            Some(VideoTimestamp::from_micros(1_634_970))
+        );
+        let pos_regex = Regex::new(SHOWINFO_POS_PATTERN).unwrap();
+        assert_eq!(parse_showinfo_pos(&pos_regex, line), None);
+        let line = "[Parsed_showinfo_0 @ 0x5581] n:   3 pts:  24024 pts_time:8.008 \
+                    duration:1001 duration_time:0.0333667 pos:  1838276 fmt:yuv420p";
+        assert_eq!(parse_showinfo_pos(&pos_regex, line), Some(1_838_276));
+        assert_eq!(
+            parse_showinfo_line(
+                &regex,
+                "frame=  120 fps= 60 q=2.0 size=N/A time=00:00:04.00"
+            ),
+            None
+        );
+    }
+
+    /// What `find-clips` does for a fixed scan: probe, extract and index thumbnails, score them.
+    /// The frames come from the replay empty, so the test draws them before scoring.
+    #[test]
+    fn test_probe_thumbnails_score() {
+        let dir = std::env::temp_dir().join(format!("thumbnail-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let json = r#"{
+            "streams": [{"index": 0, "codec_type": "video", "avg_frame_rate": "1/1",
+                         "width": 64, "height": 36}],
+            "format": {
+                "filename": "scan.mp4", "nb_streams": 1, "nb_programs": 0,
+                "format_name": "mov,mp4", "format_long_name": "QuickTime / MOV",
+                "start_time": "0.000000", "duration": "3.000000", "size": "1024",
+                "bit_rate": "N/A", "probe_score": 100, "tags": {}
+            }
+        }"#;
+        test_replay().push(Recording::success(
+            "ffprobe -v error -print_format json -show_format -show_streams scan.mp4",
+            &json.lines().collect::<Vec<_>>(),
+        ));
+        let thumbs = (1..=3)
+            .map(|i| dir.join(format!("thumb{:04}.jpg", i)))
+            .collect::<Vec<_>>();
+        test_replay().push(
+            Recording {
+                stderr: (0..3)
+                    .map(|i| {
+                        format!(
+                            "[Parsed_showinfo_1 @ 0x5581] n:   {} pts:  {} pts_time:{}.5 \
+                             duration:1 duration_time:1 fmt:yuv420p",
+                            i,
+                            2 * i + 1,
+                            i
+                        )
+                    })
+                    .collect(),
+                ..Recording::success(
+                    &format!(
+                        "ffmpeg -i scan.mp4 -vf \
+                         'select=isnan(prev_selected_t)+gte(t-prev_selected_t\\,0.999000),showinfo' \
+                         -vsync 0 -qscale:v 2 -f image2 {}",
+                        dir.join("thumb%04d.jpg").display()
+                    ),
+                    &[] as &[&str],
+                )
+            }
+            .with_outputs(&thumbs),
+        );
+
+        let probe = probe_format("scan.mp4").unwrap();
+        assert_eq!(probe.duration().unwrap(), VideoTimestamp::from_seconds(3));
+        let fps = probe.video_frame_rate().unwrap();
+        let mut thumbnails = generate_thumbnails("scan.mp4", dir.clone(), fps).unwrap();
+        thumbnails.sort_by_key(|t| t.seq);
+        assert_eq!(
+            thumbnails.iter().map(|t| t.timestamp).collect::<Vec<_>>(),
+            [500, 1500, 2500].map(VideoTimestamp::from_millis)
+        );
+
+        let colors = [[200, 30, 30], [255, 255, 255], [30, 30, 200]];
+        for (thumbnail, color) in thumbnails.iter().zip(colors) {
+            image::RgbImage::from_pixel(64, 36, image::Rgb(color))
+                .save(&thumbnail.path)
+                .unwrap();
+        }
+        let mictests = thumbnails
+            .iter()
+            .map(|t| image_file_mictest_score(&t.path).unwrap() >= MICTEST_SCORE_THRESHOLD)
+            .collect::<Vec<_>>();
+        assert_eq!(mictests, [false, true, false]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_collect_planned_thumbnails() {
+        let dir = std::env::temp_dir().join(format!("planned-thumbs-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for i in 1..=2 {
+            std::fs::File::create(dir.join(format!("thumb{:04}.jpg", i))).unwrap();
+        }
+        std::fs::write(
+            dir.join(SHOWINFO_LOG_FILE),
+            "Input #0, mov,mp4,m4a,3gp,3g2,mj2, from 'in.mp4':\n\
+             [Parsed_showinfo_1 @ 0x5581] n:   0 pts:  0 pts_time:0 duration:1001\n\
+             [Parsed_showinfo_1 @ 0x5581] n:   1 pts:  1001 pts_time:1.001 duration:1001\n\
+             frame=    2 fps=0.0 q=2.0 Lsize=N/A time=00:00:01.00\n",
+        )
+        .unwrap();
+        let mut thumbnails = collect_thumbnail_into(dir.clone(), (1, 1)).unwrap();
+        thumbnails.sort_by_key(|t| t.seq);
+        assert_eq!(
+            thumbnails.iter().map(|t| t.timestamp).collect::<Vec<_>>(),
+            [0, 1001].map(VideoTimestamp::from_millis)
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 }
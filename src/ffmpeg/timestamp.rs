@@ -0,0 +1,375 @@
+use std::{
+    fmt,
+    ops::{Add, Sub},
+    str::FromStr,
+    time::Duration,
+};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+const MICROS_PER_SECOND: u64 = 1_000_000;
+
+/// A non-negative position in a video with microsecond resolution.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct VideoTimestamp {
+    micros: u64,
+}
+
+impl VideoTimestamp {
+    pub const fn zero() -> Self {
+        Self { micros: 0 }
+    }
+    pub const fn from_micros(micros: u64) -> Self {
+        Self { micros }
+    }
+    pub const fn from_millis(millis: u64) -> Self {
+        Self {
+            micros: millis * 1000,
+        }
+    }
+    pub const fn from_seconds(seconds: u64) -> Self {
+        Self {
+            micros: seconds * MICROS_PER_SECOND,
+        }
+    }
+    /// Converts from floating point seconds, clamping negative values to zero.
+    pub fn from_float_seconds(seconds: f64) -> Self {
+        Self {
+            micros: (seconds.max(0.0) * MICROS_PER_SECOND as f64).round() as u64,
+        }
+    }
+    pub const fn as_micros(&self) -> u64 {
+        self.micros
+    }
+    pub fn as_float_seconds(&self) -> f64 {
+        self.micros as f64 / MICROS_PER_SECOND as f64
+    }
+    /// Timestamp of the start of `frame` at a constant frame rate of `fps.0 / fps.1`.
+    pub fn from_frame(frame: u64, fps: (u64, u64)) -> Self {
+        let micros = frame as u128 * fps.1 as u128 * MICROS_PER_SECOND as u128 / fps.0 as u128;
+        Self {
+            micros: micros as u64,
+        }
+    }
+    /// Index of the frame nearest to this timestamp at a constant frame rate of `fps.0 / fps.1`.
+    pub fn as_frame(&self, fps: (u64, u64)) -> u64 {
+        let scaled = self.micros as u128 * fps.0 as u128;
+        let unit = fps.1 as u128 * MICROS_PER_SECOND as u128;
+        ((scaled + unit / 2) / unit) as u64
+    }
+    pub fn checked_add_micros(&self, micros: i64) -> Option<Self> {
+        self.micros
+            .checked_add_signed(micros)
+            .map(|micros| Self { micros })
+    }
+    pub fn saturating_add_micros(&self, micros: i64) -> Self {
+        Self {
+            micros: self.micros.saturating_add_signed(micros),
+        }
+    }
+    pub fn checked_add_seconds(&self, seconds: i64) -> Option<Self> {
+        self.checked_add_micros(seconds.checked_mul(MICROS_PER_SECOND as i64)?)
+    }
+    /// Adds a signed number of seconds, saturating at zero.
+    pub fn add_seconds(&self, seconds: i64) -> Self {
+        self.saturating_add_micros(seconds.saturating_mul(MICROS_PER_SECOND as i64))
+    }
+    pub fn checked_sub(&self, other: &Self) -> Option<Duration> {
+        self.micros
+            .checked_sub(other.micros)
+            .map(Duration::from_micros)
+    }
+    pub fn saturating_sub(&self, other: &Self) -> Duration {
+        self.checked_sub(other).unwrap_or_default()
+    }
+    pub fn checked_add_duration(&self, duration: Duration) -> Option<Self> {
+        let micros = u64::try_from(duration.as_micros()).ok()?;
+        self.micros
+            .checked_add(micros)
+            .map(|micros| Self { micros })
+    }
+    pub fn checked_sub_duration(&self, duration: Duration) -> Option<Self> {
+        let micros = u64::try_from(duration.as_micros()).ok()?;
+        self.micros
+            .checked_sub(micros)
+            .map(|micros| Self { micros })
+    }
+    /// Moves back by `duration`, stopping at zero.
+    pub fn saturating_sub_duration(&self, duration: Duration) -> Self {
+        self.checked_sub_duration(duration).unwrap_or_default()
+    }
+    /// Signed distance from `other` to `self` in microseconds.
+    pub fn micros_since(&self, other: &Self) -> i64 {
+        self.micros as i64 - other.micros as i64
+    }
+    pub fn as_hms(&self) -> (u64, u64, u64) {
+        let total = self.micros / MICROS_PER_SECOND;
+        let hours = total / 3600;
+        let minutes = (total - hours * 3600) / 60;
+        let seconds = total - hours * 3600 - minutes * 60;
+        (hours, minutes, seconds)
+    }
+    pub fn subsec_millis(&self) -> u64 {
+        self.micros % MICROS_PER_SECOND / 1000
+    }
+    pub fn as_ffmpeg_arg(&self) -> String {
+        let (hours, minutes, seconds) = self.as_hms();
+        format!(
+            "{:02}:{:02}:{:02}.{:03}",
+            hours,
+            minutes,
+            seconds,
+            self.subsec_millis()
+        )
+    }
+}
+
+impl fmt::Display for VideoTimestamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.as_ffmpeg_arg())
+    }
+}
+
+/// Parses `HH:MM:SS[.fff]`, `MM:SS[.fff]` or plain `SS[.fff]` seconds.
+impl FromStr for VideoTimestamp {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || anyhow::anyhow!("Invalid timestamp: {:?}", s);
+        let parts = s.trim().split(':').collect::<Vec<_>>();
+        if parts.len() > 3 {
+            return Err(invalid());
+        }
+        let (last, whole) = parts.split_last().ok_or_else(invalid)?;
+
+        let (secs, frac) = last.split_once('.').unwrap_or((last, ""));
+        if secs.is_empty() || !secs.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(invalid());
+        }
+        if !frac.bytes().all(|b| b.is_ascii_digit()) || frac.len() > 6 {
+            return Err(invalid());
+        }
+        let secs = secs.parse::<u64>().map_err(|_| invalid())?;
+        let frac_micros = if frac.is_empty() {
+            0
+        } else {
+            frac.parse::<u64>().map_err(|_| invalid())? * 10u64.pow(6 - frac.len() as u32)
+        };
+
+        let mut total = 0u64;
+        for (i, part) in whole.iter().enumerate() {
+            if part.is_empty() || !part.bytes().all(|b| b.is_ascii_digit()) {
+                return Err(invalid());
+            }
+            let value = part.parse::<u64>().map_err(|_| invalid())?;
+            // everything but the leading component must be below 60
+            if i > 0 && value >= 60 {
+                return Err(invalid());
+            }
+            total = total.checked_mul(60).ok_or_else(invalid)? + value;
+        }
+        if whole.is_empty() {
+            total = secs;
+        } else if secs < 60 {
+            total = total.checked_mul(60).ok_or_else(invalid)? + secs;
+        } else {
+            return Err(invalid());
+        }
+
+        let micros = total
+            .checked_mul(MICROS_PER_SECOND)
+            .and_then(|m| m.checked_add(frac_micros))
+            .ok_or_else(invalid)?;
+        Ok(Self { micros })
+    }
+}
+
+impl Add for VideoTimestamp {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        self.checked_add_duration(rhs.into())
+            .expect("overflow when adding timestamps")
+    }
+}
+
+impl Add<Duration> for VideoTimestamp {
+    type Output = Self;
+
+    fn add(self, rhs: Duration) -> Self::Output {
+        self.checked_add_duration(rhs)
+            .expect("overflow when adding duration to timestamp")
+    }
+}
+
+/// Panics if `rhs` is later, see [`VideoTimestamp::saturating_sub`].
+impl Sub for VideoTimestamp {
+    type Output = Duration;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        self.checked_sub(&rhs)
+            .expect("subtracted a later timestamp")
+    }
+}
+
+/// Panics below zero, see [`VideoTimestamp::saturating_sub_duration`].
+impl Sub<Duration> for VideoTimestamp {
+    type Output = Self;
+
+    fn sub(self, rhs: Duration) -> Self::Output {
+        self.checked_sub_duration(rhs)
+            .expect("overflow when subtracting duration from timestamp")
+    }
+}
+
+impl From<Duration> for VideoTimestamp {
+    fn from(duration: Duration) -> Self {
+        Self {
+            micros: duration.as_micros() as u64,
+        }
+    }
+}
+
+impl From<VideoTimestamp> for Duration {
+    fn from(timestamp: VideoTimestamp) -> Self {
+        Duration::from_micros(timestamp.micros)
+    }
+}
+
+/// On-disk form, kept compatible with clip files written when timestamps were stored as
+/// seconds and milliseconds.
+#[derive(Serialize, Deserialize)]
+struct SerializedTimestamp {
+    seconds: u64,
+    milliseconds: u64,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    microseconds: u64,
+}
+
+fn is_zero(v: &u64) -> bool {
+    *v == 0
+}
+
+impl Serialize for VideoTimestamp {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        SerializedTimestamp {
+            seconds: self.micros / MICROS_PER_SECOND,
+            milliseconds: self.subsec_millis(),
+            microseconds: self.micros % 1000,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for VideoTimestamp {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = SerializedTimestamp::deserialize(deserializer)?;
+        if s.milliseconds >= 1000 || s.microseconds >= 1000 {
+            return Err(serde::de::Error::custom(
+                "milliseconds and microseconds must be below 1000",
+            ));
+        }
+        s.seconds
+            .checked_mul(MICROS_PER_SECOND)
+            .and_then(|micros| micros.checked_add(s.milliseconds * 1000 + s.microseconds))
+            .map(|micros| Self { micros })
+            .ok_or_else(|| serde::de::Error::custom("timestamp out of range"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let parse = |s: &str| s.parse::<VideoTimestamp>().ok();
+        assert_eq!(
+            parse("01:02:03.456"),
+            Some(VideoTimestamp::from_millis(3_723_456))
+        );
+        assert_eq!(parse("1:02:03"), Some(VideoTimestamp::from_seconds(3723)));
+        assert_eq!(parse("12:05"), Some(VideoTimestamp::from_seconds(725)));
+        assert_eq!(parse("90:00"), Some(VideoTimestamp::from_seconds(5400)));
+        assert_eq!(parse("42.5"), Some(VideoTimestamp::from_millis(42_500)));
+        assert_eq!(parse("0.000001"), Some(VideoTimestamp::from_micros(1)));
+        assert_eq!(parse("1:60"), None);
+        assert_eq!(parse("1:60:00"), None);
+        assert_eq!(parse("1:2:3:4"), None);
+        assert_eq!(parse("-1"), None);
+        assert_eq!(parse(""), None);
+        assert_eq!(parse("1.2.3"), None);
+    }
+
+    #[test]
+    fn test_frames() {
+        let ntsc = (30000, 1001);
+        let t = VideoTimestamp::from_frame(300, ntsc);
+        assert_eq!(t, VideoTimestamp::from_micros(10_010_000));
+        assert_eq!(t.as_frame(ntsc), 300);
+        for frame in [0, 1, 29, 1799, 107_892] {
+            assert_eq!(
+                VideoTimestamp::from_frame(frame, ntsc).as_frame(ntsc),
+                frame
+            );
+            assert_eq!(
+                VideoTimestamp::from_frame(frame, (25, 1)).as_frame((25, 1)),
+                frame
+            );
+        }
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        let t = VideoTimestamp::from_millis(1500);
+        assert_eq!(t.checked_add_seconds(-2), None);
+        assert_eq!(t.add_seconds(-2), VideoTimestamp::zero());
+        assert_eq!(t.add_seconds(1), VideoTimestamp::from_millis(2500));
+        assert_eq!(
+            t - VideoTimestamp::from_millis(500),
+            Duration::from_millis(1000)
+        );
+        assert_eq!(VideoTimestamp::zero().saturating_sub(&t), Duration::ZERO);
+        assert_eq!(VideoTimestamp::zero().checked_sub(&t), None);
+        assert_eq!(
+            t + Duration::from_millis(250),
+            VideoTimestamp::from_millis(1750)
+        );
+        assert_eq!(
+            t.saturating_sub_duration(Duration::from_secs(5)),
+            VideoTimestamp::zero()
+        );
+        assert_eq!(t.checked_sub_duration(Duration::from_secs(5)), None);
+        assert_eq!(
+            VideoTimestamp::from_micros(u64::MAX).checked_add_duration(Duration::from_micros(1)),
+            None
+        );
+        assert!(std::panic::catch_unwind(|| t - Duration::from_secs(5)).is_err());
+        assert!(std::panic::catch_unwind(|| VideoTimestamp::zero() - t).is_err());
+        assert!(std::panic::catch_unwind(|| VideoTimestamp::from_micros(u64::MAX) + t).is_err());
+        assert_eq!(t.micros_since(&VideoTimestamp::from_seconds(2)), -500_000);
+    }
+
+    #[test]
+    fn test_serde_compat() {
+        let t: VideoTimestamp =
+            serde_json::from_str(r#"{"seconds":290,"milliseconds":317}"#).unwrap();
+        assert_eq!(t, VideoTimestamp::from_millis(290_317));
+        assert_eq!(
+            serde_json::to_string(&t).unwrap(),
+            r#"{"seconds":290,"milliseconds":317}"#
+        );
+        let t = VideoTimestamp::from_micros(1_000_001);
+        assert_eq!(
+            serde_json::from_str::<VideoTimestamp>(&serde_json::to_string(&t).unwrap()).unwrap(),
+            t
+        );
+        for out_of_range in [
+            r#"{"seconds":1,"milliseconds":1000}"#,
+            r#"{"seconds":1,"milliseconds":0,"microseconds":1000}"#,
+            r#"{"seconds":18446744073709551615,"milliseconds":0}"#,
+        ] {
+            assert!(serde_json::from_str::<VideoTimestamp>(out_of_range).is_err());
+        }
+    }
+}
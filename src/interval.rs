@@ -0,0 +1,355 @@
+use std::{fmt, time::Duration};
+
+use serde::{Deserialize, Serialize};
+
+use crate::ffmpeg::VideoTimestamp;
+
+/// A non-empty half-open range `[start, end)` of a video, serialized as a `[start, end]` pair.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(
+    try_from = "(VideoTimestamp, VideoTimestamp)",
+    into = "(VideoTimestamp, VideoTimestamp)"
+)]
+pub struct TimeRange {
+    start: VideoTimestamp,
+    end: VideoTimestamp,
+}
+
+impl TimeRange {
+    pub fn new(start: VideoTimestamp, end: VideoTimestamp) -> anyhow::Result<Self> {
+        if start >= end {
+            return Err(anyhow::anyhow!(
+                "Range start {} is not before its end {}",
+                start.as_ffmpeg_arg(),
+                end.as_ffmpeg_arg()
+            ));
+        }
+        Ok(Self { start, end })
+    }
+
+    pub fn start(&self) -> &VideoTimestamp {
+        &self.start
+    }
+
+    pub fn end(&self) -> &VideoTimestamp {
+        &self.end
+    }
+
+    pub fn duration(&self) -> Duration {
+        self.end - self.start
+    }
+
+    pub fn contains(&self, timestamp: &VideoTimestamp) -> bool {
+        &self.start <= timestamp && timestamp < &self.end
+    }
+
+    pub fn overlaps(&self, other: &TimeRange) -> bool {
+        self.start < other.end && other.start < self.end
+    }
+}
+
+impl TryFrom<(VideoTimestamp, VideoTimestamp)> for TimeRange {
+    type Error = anyhow::Error;
+
+    fn try_from((start, end): (VideoTimestamp, VideoTimestamp)) -> Result<Self, Self::Error> {
+        Self::new(start, end)
+    }
+}
+
+impl From<TimeRange> for (VideoTimestamp, VideoTimestamp) {
+    fn from(range: TimeRange) -> Self {
+        (range.start, range.end)
+    }
+}
+
+impl fmt::Display for TimeRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}-{}",
+            self.start.as_ffmpeg_arg(),
+            self.end.as_ffmpeg_arg()
+        )
+    }
+}
+
+/// A set of time ranges, kept sorted with overlapping and touching ranges merged.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "Vec<TimeRange>", into = "Vec<TimeRange>")]
+pub struct IntervalSet {
+    ranges: Vec<TimeRange>,
+}
+
+impl IntervalSet {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_ranges<I: IntoIterator<Item = TimeRange>>(ranges: I) -> Self {
+        let mut sorted = ranges.into_iter().collect::<Vec<_>>();
+        sorted.sort();
+        let mut merged: Vec<TimeRange> = Vec::with_capacity(sorted.len());
+        for range in sorted {
+            match merged.last_mut() {
+                Some(last) if range.start <= last.end => {
+                    if range.end > last.end {
+                        last.end = range.end;
+                    }
+                }
+                _ => merged.push(range),
+            }
+        }
+        Self { ranges: merged }
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, TimeRange> {
+        self.ranges.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.ranges.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    pub fn total_duration(&self) -> Duration {
+        self.ranges.iter().map(|r| r.duration()).sum()
+    }
+
+    pub fn contains(&self, timestamp: &VideoTimestamp) -> bool {
+        self.ranges.iter().any(|r| r.contains(timestamp))
+    }
+
+    pub fn insert(&mut self, range: TimeRange) {
+        *self = Self::from_ranges(self.ranges.drain(..).chain(std::iter::once(range)));
+    }
+
+    pub fn union(&self, other: &IntervalSet) -> IntervalSet {
+        Self::from_ranges(self.ranges.iter().chain(other.ranges.iter()).cloned())
+    }
+
+    pub fn intersection(&self, other: &IntervalSet) -> IntervalSet {
+        let mut ranges = Vec::new();
+        let (mut i, mut j) = (0, 0);
+        while i < self.ranges.len() && j < other.ranges.len() {
+            let (a, b) = (&self.ranges[i], &other.ranges[j]);
+            let start = (&a.start).max(&b.start);
+            let end = (&a.end).min(&b.end);
+            if start < end {
+                ranges.push(TimeRange {
+                    start: *start,
+                    end: *end,
+                });
+            }
+            if a.end < b.end {
+                i += 1;
+            } else {
+                j += 1;
+            }
+        }
+        Self { ranges }
+    }
+
+    pub fn subtract(&self, other: &IntervalSet) -> IntervalSet {
+        let mut ranges = Vec::new();
+        for range in &self.ranges {
+            let mut start = range.start;
+            for cut in other.ranges.iter().filter(|cut| cut.overlaps(range)) {
+                if cut.start > start {
+                    ranges.push(TimeRange {
+                        start,
+                        end: cut.start,
+                    });
+                }
+                start = cut.end;
+            }
+            if start < range.end {
+                ranges.push(TimeRange {
+                    start,
+                    end: range.end,
+                });
+            }
+        }
+        Self { ranges }
+    }
+
+    /// Widens every range by `before` and `after`, never going below zero.
+    pub fn pad(&self, before: Duration, after: Duration) -> IntervalSet {
+        Self::from_ranges(self.ranges.iter().map(|r| TimeRange {
+            start: r.start.saturating_sub_duration(before),
+            end: r.end + after,
+        }))
+    }
+
+    pub fn clamp(&self, bounds: &TimeRange) -> IntervalSet {
+        self.intersection(&Self::from(vec![bounds.clone()]))
+    }
+
+    /// Merges neighbouring ranges separated by at most `max_gap`.
+    pub fn merge_adjacent(&self, max_gap: Duration) -> IntervalSet {
+        let mut merged: Vec<TimeRange> = Vec::with_capacity(self.ranges.len());
+        for range in &self.ranges {
+            match merged.last_mut() {
+                Some(last) if range.start - last.end <= max_gap => {
+                    last.end = range.end;
+                }
+                _ => merged.push(range.clone()),
+            }
+        }
+        Self { ranges: merged }
+    }
+}
+
+impl From<Vec<TimeRange>> for IntervalSet {
+    fn from(ranges: Vec<TimeRange>) -> Self {
+        Self::from_ranges(ranges)
+    }
+}
+
+impl From<IntervalSet> for Vec<TimeRange> {
+    fn from(set: IntervalSet) -> Self {
+        set.ranges
+    }
+}
+
+impl FromIterator<TimeRange> for IntervalSet {
+    fn from_iter<I: IntoIterator<Item = TimeRange>>(iter: I) -> Self {
+        Self::from_ranges(iter)
+    }
+}
+
+impl IntoIterator for IntervalSet {
+    type Item = TimeRange;
+    type IntoIter = std::vec::IntoIter<TimeRange>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.ranges.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a IntervalSet {
+    type Item = &'a TimeRange;
+    type IntoIter = std::slice::Iter<'a, TimeRange>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.ranges.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    fn ts(ms: u64) -> VideoTimestamp {
+        VideoTimestamp::from_millis(ms)
+    }
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    fn range(start: u64, end: u64) -> TimeRange {
+        TimeRange::new(ts(start), ts(end)).unwrap()
+    }
+
+    fn interval_set() -> impl Strategy<Value = IntervalSet> {
+        prop::collection::vec((0u64..10_000, 1u64..2_000), 0..8).prop_map(|ranges| {
+            ranges
+                .into_iter()
+                .map(|(start, len)| range(start, start + len))
+                .collect()
+        })
+    }
+
+    fn assert_normalized(set: &IntervalSet) {
+        for r in set.iter() {
+            assert!(r.start < r.end);
+        }
+        for (a, b) in set.iter().zip(set.iter().skip(1)) {
+            assert!(a.end < b.start);
+        }
+    }
+
+    #[test]
+    fn test_time_range_serde() {
+        let json = r#"[{"seconds":290,"milliseconds":317},{"seconds":319,"milliseconds":383}]"#;
+        let r: TimeRange = serde_json::from_str(json).unwrap();
+        assert_eq!(r, range(290_317, 319_383));
+        assert_eq!(serde_json::to_string(&r).unwrap(), json);
+
+        let backwards = r#"[{"seconds":2,"milliseconds":0},{"seconds":1,"milliseconds":0}]"#;
+        assert!(serde_json::from_str::<TimeRange>(backwards).is_err());
+    }
+
+    #[test]
+    fn test_interval_set_ops() {
+        let a = IntervalSet::from(vec![range(0, 1000), range(500, 2000), range(3000, 4000)]);
+        assert_eq!(
+            a,
+            IntervalSet::from(vec![range(0, 2000), range(3000, 4000)])
+        );
+
+        let b = IntervalSet::from(vec![range(1500, 3500)]);
+        assert_eq!(
+            a.intersection(&b),
+            IntervalSet::from(vec![range(1500, 2000), range(3000, 3500)])
+        );
+        assert_eq!(
+            a.subtract(&b),
+            IntervalSet::from(vec![range(0, 1500), range(3500, 4000)])
+        );
+        assert_eq!(a.union(&b), IntervalSet::from(vec![range(0, 4000)]));
+        assert_eq!(
+            a.pad(ms(500), ms(250)),
+            IntervalSet::from(vec![range(0, 2250), range(2500, 4250)])
+        );
+        assert_eq!(
+            a.clamp(&range(1000, 3500)),
+            IntervalSet::from(vec![range(1000, 2000), range(3000, 3500)])
+        );
+        assert_eq!(a.merge_adjacent(ms(500)), a);
+        assert_eq!(
+            a.merge_adjacent(ms(1000)),
+            IntervalSet::from(vec![range(0, 4000)])
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn prop_set_ops_match_membership(a in interval_set(), b in interval_set(), t in 0u64..12_000) {
+            let t = ts(t);
+            let union = a.union(&b);
+            let intersection = a.intersection(&b);
+            let difference = a.subtract(&b);
+            assert_normalized(&union);
+            assert_normalized(&intersection);
+            assert_normalized(&difference);
+            prop_assert_eq!(union.contains(&t), a.contains(&t) || b.contains(&t));
+            prop_assert_eq!(intersection.contains(&t), a.contains(&t) && b.contains(&t));
+            prop_assert_eq!(difference.contains(&t), a.contains(&t) && !b.contains(&t));
+        }
+
+        #[test]
+        fn prop_pad_and_merge_cover_original(a in interval_set(), before in 0u64..1000, after in 0u64..1000) {
+            let padded = a.pad(ms(before), ms(after));
+            assert_normalized(&padded);
+            prop_assert!(a.subtract(&padded).is_empty());
+            prop_assert_eq!(a.pad(Duration::ZERO, Duration::ZERO), a.clone());
+
+            let merged = a.merge_adjacent(ms(before));
+            assert_normalized(&merged);
+            prop_assert!(merged.len() <= a.len());
+            prop_assert!(a.subtract(&merged).is_empty());
+        }
+
+        #[test]
+        fn prop_serde_roundtrip(a in interval_set()) {
+            let json = serde_json::to_string(&a).unwrap();
+            prop_assert_eq!(serde_json::from_str::<IntervalSet>(&json).unwrap(), a);
+        }
+    }
+}
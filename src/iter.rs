@@ -1,3 +1,5 @@
+use serde::{Deserialize, Serialize};
+
 pub fn iter_continuous_range<T, I, FC>(iter: I, is_continuous: FC) -> impl Iterator<Item = (T, T)>
 where
     T: Clone,
@@ -19,6 +21,59 @@ where
     })
 }
 
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct HysteresisParams {
+    /// A range opens at a sample scoring at least this much.
+    pub enter: f64,
+    /// An open range continues through samples scoring at least this much.
+    pub exit: f64,
+    /// Longest run of samples below `exit`, in seconds, that is bridged instead of closing the range.
+    pub max_gap: f64,
+    /// Ranges shorter than this, in seconds, are dropped.
+    pub min_duration: f64,
+}
+
+/// Groups time-ordered scored samples into ranges using separate enter/exit thresholds.
+///
+/// Yields the first and last sample scoring at least `exit` of every range; the samples
+/// inside a bridged gap are not reported.
+pub fn iter_hysteresis_range<T, I, FT, FS>(
+    iter: I,
+    params: HysteresisParams,
+    time_of: FT,
+    score_of: FS,
+) -> impl Iterator<Item = (T, T)>
+where
+    T: Clone,
+    I: Iterator<Item = T>,
+    FT: Fn(&T) -> f64,
+    FS: Fn(&T) -> f64,
+{
+    let mut iter = iter.peekable();
+    std::iter::from_fn(move || loop {
+        let first = loop {
+            let item = iter.next()?;
+            if score_of(&item) >= params.enter {
+                break item;
+            }
+        };
+        let mut last = first.clone();
+        while let Some(next) = iter.peek() {
+            if score_of(next) >= params.exit {
+                last = iter.next().unwrap();
+            } else if time_of(next) - time_of(&last) <= params.max_gap {
+                iter.next();
+            } else {
+                break;
+            }
+        }
+        if time_of(&last) - time_of(&first) >= params.min_duration {
+            return Some((first, last));
+        }
+    })
+}
+
+#[cfg(test)]
 mod tests {
     use super::*;
 
@@ -35,4 +90,44 @@ mod tests {
         let mut iter = iter_continuous_range(v.iter(), |a, b| **a + 1 == **b);
         assert_eq!(iter.next(), None);
     }
+
+    #[test]
+    fn test_iter_hysteresis_range() {
+        let params = HysteresisParams {
+            enter: 0.8,
+            exit: 0.4,
+            max_gap: 2.0,
+            min_duration: 1.0,
+        };
+        let v = vec![
+            (0.0, 0.5),
+            (1.0, 0.9),
+            (2.0, 0.5),
+            (3.0, 0.1),
+            (4.0, 0.6),
+            (5.0, 0.0),
+            (6.0, 0.0),
+            (7.0, 0.0),
+            (8.0, 0.9),
+            (9.0, 0.0),
+            (10.0, 0.0),
+            (11.0, 0.0),
+            (12.0, 0.9),
+            (13.0, 1.0),
+        ];
+        let mut iter = iter_hysteresis_range(v.iter(), params, |x| x.0, |x| x.1);
+        assert_eq!(iter.next(), Some((&(1.0, 0.9), &(4.0, 0.6))));
+        assert_eq!(iter.next(), Some((&(12.0, 0.9), &(13.0, 1.0))));
+        assert_eq!(iter.next(), None);
+
+        let params = HysteresisParams {
+            min_duration: 0.0,
+            ..params
+        };
+        let mut iter = iter_hysteresis_range(v.iter(), params, |x| x.0, |x| x.1);
+        assert_eq!(iter.next(), Some((&(1.0, 0.9), &(4.0, 0.6))));
+        assert_eq!(iter.next(), Some((&(8.0, 0.9), &(8.0, 0.9))));
+        assert_eq!(iter.next(), Some((&(12.0, 0.9), &(13.0, 1.0))));
+        assert_eq!(iter.next(), None);
+    }
 }
@@ -1,4 +1,16 @@
+pub mod cancel;
+pub mod catalog;
+pub mod clips;
+pub mod dataset;
+pub mod diff;
 pub mod download;
 pub mod ffmpeg;
+pub mod interval;
 pub mod iter;
+pub mod lint;
+pub mod overrides;
+pub mod pipeline;
 pub mod recog;
+pub mod refine;
+pub mod runner;
+pub mod sampler;
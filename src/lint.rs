@@ -0,0 +1,270 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::{clips::ClipsInfo, ffmpeg::VideoTimestamp, interval::TimeRange};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Finding {
+    TooManyRanges {
+        count: usize,
+    },
+    LongRange {
+        range: TimeRange,
+    },
+    ShortRange {
+        range: TimeRange,
+    },
+    /// Starts in the last part of the video, where mic tests are rare.
+    LateRange {
+        range: TimeRange,
+        position: f64,
+    },
+    Overlapping {
+        first: TimeRange,
+        second: TimeRange,
+    },
+    PastEnd {
+        range: TimeRange,
+        duration: VideoTimestamp,
+    },
+    /// A video uploaded after mic tests became a regular segment has none.
+    NoRanges,
+}
+
+impl Finding {
+    pub fn severity(&self) -> Severity {
+        match self {
+            Finding::Overlapping { .. } | Finding::PastEnd { .. } => Severity::Error,
+            Finding::TooManyRanges { .. } | Finding::LongRange { .. } => Severity::Warning,
+            Finding::ShortRange { .. } | Finding::LateRange { .. } | Finding::NoRanges => {
+                Severity::Info
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for Finding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Finding::TooManyRanges { count } => write!(f, "{} ranges", count),
+            Finding::LongRange { range } => {
+                write!(f, "{} lasts {:.1}s", range, range.duration().as_secs_f64())
+            }
+            Finding::ShortRange { range } => {
+                write!(f, "{} lasts {:.1}s", range, range.duration().as_secs_f64())
+            }
+            Finding::LateRange { range, position } => {
+                write!(
+                    f,
+                    "{} starts at {:.0}% of the video",
+                    range,
+                    position * 100.0
+                )
+            }
+            Finding::Overlapping { first, second } => write!(f, "{} overlaps {}", first, second),
+            Finding::PastEnd { range, duration } => {
+                write!(f, "{} ends after the video at {}", range, duration)
+            }
+            Finding::NoRanges => write!(f, "no mic test"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LintConfig {
+    pub max_ranges: usize,
+    pub min_duration: Duration,
+    pub max_duration: Duration,
+    /// Fraction of the video after which a range start is reported.
+    pub max_position: f64,
+    /// Videos published from this date on are expected to have a mic test.
+    pub expect_mictest_since: Option<DateTime<Utc>>,
+}
+
+impl Default for LintConfig {
+    fn default() -> Self {
+        Self {
+            max_ranges: 3,
+            min_duration: Duration::from_secs(5),
+            max_duration: Duration::from_secs(90),
+            max_position: 0.9,
+            expect_mictest_since: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LintReport {
+    pub video_id: String,
+    pub findings: Vec<Finding>,
+}
+
+impl LintReport {
+    pub fn severity(&self) -> Option<Severity> {
+        self.findings.iter().map(|f| f.severity()).max()
+    }
+}
+
+/// Checks the ranges of one video. `duration` should come from probing the video when the
+/// clips file doesn't record it.
+pub fn lint_clips(
+    info: &ClipsInfo,
+    duration: Option<VideoTimestamp>,
+    published_at: Option<DateTime<Utc>>,
+    config: &LintConfig,
+) -> Vec<Finding> {
+    let mut findings = Vec::new();
+    let duration = duration.or(info.provenance.as_ref().map(|p| p.source.duration));
+
+    if info.ranges.is_empty() {
+        if let (Some(since), Some(published_at)) = (config.expect_mictest_since, published_at) {
+            if published_at >= since {
+                findings.push(Finding::NoRanges);
+            }
+        }
+    }
+    if info.ranges.len() > config.max_ranges {
+        findings.push(Finding::TooManyRanges {
+            count: info.ranges.len(),
+        });
+    }
+    for (a, b) in info.ranges.iter().zip(info.ranges.iter().skip(1)) {
+        if a.range.overlaps(&b.range) {
+            findings.push(Finding::Overlapping {
+                first: a.range.clone(),
+                second: b.range.clone(),
+            });
+        }
+    }
+    for range in info.ranges.iter().map(|r| &r.range) {
+        if range.duration() > config.max_duration {
+            findings.push(Finding::LongRange {
+                range: range.clone(),
+            });
+        } else if range.duration() < config.min_duration {
+            findings.push(Finding::ShortRange {
+                range: range.clone(),
+            });
+        }
+        let Some(duration) = duration else {
+            continue;
+        };
+        if *range.end() > duration {
+            findings.push(Finding::PastEnd {
+                range: range.clone(),
+                duration,
+            });
+        } else {
+            let position = range.start().as_float_seconds() / duration.as_float_seconds();
+            if position > config.max_position {
+                findings.push(Finding::LateRange {
+                    range: range.clone(),
+                    position,
+                });
+            }
+        }
+    }
+    findings
+}
+
+/// Orders reports for review: worst severity first, then most findings.
+pub fn prioritize(reports: &mut [LintReport]) {
+    reports.sort_by(|a, b| {
+        b.severity()
+            .cmp(&a.severity())
+            .then(b.findings.len().cmp(&a.findings.len()))
+            .then(a.video_id.cmp(&b.video_id))
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clips::DetectedRange;
+
+    fn range(start: u64, end: u64) -> DetectedRange {
+        DetectedRange::new(
+            TimeRange::new(
+                VideoTimestamp::from_seconds(start),
+                VideoTimestamp::from_seconds(end),
+            )
+            .unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_lint_clips() {
+        let config = LintConfig {
+            expect_mictest_since: Some("2023-01-01T00:00:00Z".parse().unwrap()),
+            ..LintConfig::default()
+        };
+        let info = ClipsInfo::new(
+            vec![range(100, 130), range(120, 124), range(900, 1300)],
+            None,
+        );
+        let findings = lint_clips(
+            &info,
+            Some(VideoTimestamp::from_seconds(1000)),
+            None,
+            &config,
+        );
+        let kinds = findings
+            .iter()
+            .map(|f| serde_json::to_value(f).unwrap()["kind"].clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            ["overlapping", "short_range", "long_range", "past_end"]
+        );
+
+        let empty = ClipsInfo::new(Vec::new(), None);
+        let recent = Some("2024-05-01T00:00:00Z".parse().unwrap());
+        let old = Some("2021-05-01T00:00:00Z".parse().unwrap());
+        assert_eq!(
+            lint_clips(&empty, None, recent, &config),
+            vec![Finding::NoRanges]
+        );
+        assert!(lint_clips(&empty, None, old, &config).is_empty());
+
+        let late = ClipsInfo::new(vec![range(950, 980)], None);
+        assert!(matches!(
+            lint_clips(
+                &late,
+                Some(VideoTimestamp::from_seconds(1000)),
+                None,
+                &config
+            )[..],
+            [Finding::LateRange { .. }]
+        ));
+    }
+
+    #[test]
+    fn test_prioritize() {
+        let report = |id: &str, findings| LintReport {
+            video_id: id.to_string(),
+            findings,
+        };
+        let mut reports = vec![
+            report("a", vec![Finding::NoRanges]),
+            report("b", vec![Finding::TooManyRanges { count: 5 }]),
+            report("c", vec![Finding::NoRanges, Finding::NoRanges]),
+        ];
+        prioritize(&mut reports);
+        let order = reports
+            .iter()
+            .map(|r| r.video_id.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(order, ["b", "c", "a"]);
+    }
+}
@@ -1,32 +1,75 @@
 use std::{
-    fs::{create_dir_all, read_dir},
-    io::Write,
+    io::IsTerminal,
     path::Path,
-    sync::Mutex,
+    sync::{Arc, Mutex},
+    time::Duration,
 };
 
-use chrono::{DateTime, FixedOffset};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
 use clap::Parser;
 use dankpods_mic_tests::{
+    cancel::{CancelToken, Cancelled},
+    catalog::{load_catalog, CatalogEntry},
+    clips::{ClipsInfo, DetectedRange, Provenance, SourceInfo},
+    dataset::{
+        clips_file, list_clip_ids, sync_clips, SyncOutcome, DATASET_CLIPS_DIR, WORKSPACE_CLIPS_DIR,
+    },
+    diff::{diff_dirs, DiffSide},
     download::download_video_with_retries,
     ffmpeg::{
-        clip::{self, make_multiple_clip},
-        concat::{concat_videos, concat_videos_filter},
+        capabilities::capabilities,
+        clip::make_multiple_clip,
+        concat::concat_videos_filter,
+        encoder::{find_profile, EncoderProfile, DEFAULT_ENCODER_PROFILE},
+        keyframes::KeyframeIndex,
         probe::probe_format,
-        thumbnail::{collect_thumbnail_into, generate_thumbnails},
+        process::shell_script,
+        progress::Progress,
+        scheduler::{configure_scheduler, scheduler, SchedulerConfig},
+        thumbnail::{collect_thumbnail_into, generate_keyframe_thumbnails, generate_thumbnails},
         VideoTimestamp,
     },
-    iter::iter_continuous_range,
-    recog::image_file_is_mictest,
+    interval::{IntervalSet, TimeRange},
+    iter::{iter_hysteresis_range, HysteresisParams},
+    lint::{lint_clips, prioritize, LintConfig, LintReport},
+    overrides::{OverrideOp, Overrides},
+    pipeline::{run_pipeline, PipelineConfig},
+    recog::{image_file_is_mictest, image_file_mictest_score, DETECTOR_NAME, DETECTOR_VERSION},
+    refine::{refine_begin, refine_end, BoundaryFlag, FrameSeeker, RefinedBoundary},
+    runner::{CommandRunner, RecordingRunner, SystemRunner},
+    sampler::{adaptive_sample, SamplingParams},
 };
 use itertools::Itertools;
-use log::info;
+use log::{debug, info, warn};
 use rayon::ThreadPoolBuilder;
 use regex::Regex;
-use serde::{Deserialize, Serialize};
 
 #[derive(Parser)]
 pub struct Cli {
+    /// Most ffmpeg and ffprobe processes running at once, one per core by default
+    #[clap(long, global = true)]
+    pub ffmpeg_jobs: Option<usize>,
+    /// Threads each ffmpeg process may decode with, chosen by ffmpeg by default
+    #[clap(long, global = true)]
+    pub ffmpeg_threads: Option<usize>,
+    /// Also write logs of ffmpeg processes that succeeded to data/logs, not only failed ones
+    #[clap(long, global = true)]
+    pub ffmpeg_log_all: bool,
+    /// Kill any single ffmpeg or ffprobe process running longer than this many seconds
+    #[clap(long, global = true)]
+    pub ffmpeg_timeout: Option<u64>,
+    /// Change nothing, only print the commands that would run as a shell script. Probes
+    /// still run, as what comes next depends on them
+    #[clap(long, global = true)]
+    pub dry_run: bool,
+    /// Write the shell script of a dry run here instead of printing it
+    #[clap(long, global = true, requires = "dry_run")]
+    pub plan_script: Option<String>,
+    /// Save every external command run and what it printed to this JSON file, to replay in
+    /// tests
+    #[clap(long, global = true)]
+    pub record_commands: Option<String>,
     #[command(subcommand)]
     pub subcommand: Commands,
 }
@@ -38,7 +81,23 @@ pub enum Commands {
     #[clap(name = "make-clips")]
     MakeClips(MakeClipsArgs),
     #[clap(name = "concat")]
-    Concat,
+    Concat(ConcatArgs),
+    #[clap(name = "catalog")]
+    Catalog,
+    #[clap(name = "override")]
+    Override(OverrideArgs),
+    /// Copy workspace ranges, with overrides applied, into the committed dataset
+    #[clap(name = "publish")]
+    Publish(SyncArgs),
+    /// Copy ranges from the committed dataset into the workspace
+    #[clap(name = "import")]
+    Import(SyncArgs),
+    /// Compare the range files of two directories
+    #[clap(name = "diff")]
+    Diff(DiffArgs),
+    /// List videos whose ranges look suspicious, most severe first
+    #[clap(name = "lint")]
+    Lint(LintArgs),
 }
 
 #[derive(Parser)]
@@ -47,6 +106,161 @@ pub struct FindClipsArgs {
     pub skip_existing_clips: bool,
     #[clap(long)]
     pub from_id: Option<String>,
+    /// Score at which a rough mic test range opens
+    #[clap(long, default_value_t = 0.6)]
+    pub enter_threshold: f64,
+    /// Score below which a rough mic test range starts closing
+    #[clap(long, default_value_t = 0.4)]
+    pub exit_threshold: f64,
+    /// Longest gap of non mic test frames, in seconds, bridged within a range
+    #[clap(long, default_value_t = 2.0)]
+    pub max_gap: f64,
+    /// Shortest rough range, in seconds, considered a mic test
+    #[clap(long, default_value_t = 4.0)]
+    pub min_duration: f64,
+    /// Scan every this many seconds first and decode every second only around score
+    /// changes, instead of decoding every second of the video
+    #[clap(long, value_parser = positive_seconds)]
+    pub coarse_interval: Option<f64>,
+    /// Score only keyframes, decoded with `-skip_frame nokey`. Keyframes are usually a few
+    /// seconds apart, so --max-gap may need raising
+    #[clap(long, conflicts_with = "coarse_interval")]
+    pub keyframes_only: bool,
+    /// Videos analysed at the same time
+    #[clap(long, short, default_value_t = 1)]
+    pub jobs: usize,
+    /// Videos downloaded at the same time
+    #[clap(long, default_value_t = 1)]
+    pub download_jobs: usize,
+    /// Downloaded videos allowed to wait for analysis
+    #[clap(long, default_value_t = 2)]
+    pub prefetch: usize,
+    /// Threads scoring frames and refining boundaries of each video, 0 uses one per core.
+    /// ffmpeg processes are limited separately by --ffmpeg-jobs
+    #[clap(long, default_value_t = 0)]
+    pub classifier_threads: usize,
+}
+
+/// Parses a number of seconds that has to be greater than zero, such as a scan interval,
+/// which would otherwise never advance.
+fn positive_seconds(value: &str) -> Result<f64, String> {
+    let seconds = value.parse::<f64>().map_err(|e| e.to_string())?;
+    if seconds > 0.0 && Duration::try_from_secs_f64(seconds).is_ok() {
+        Ok(seconds)
+    } else {
+        Err(format!("{} is not a positive number of seconds", value))
+    }
+}
+
+/// Rate of the fixed scan `find-clips` does without --coarse-interval or --keyframes-only.
+const SCAN_FPS: (u64, u64) = (1, 1);
+
+impl FindClipsArgs {
+    pub fn hysteresis_params(&self) -> HysteresisParams {
+        HysteresisParams {
+            enter: self.enter_threshold,
+            exit: self.exit_threshold,
+            max_gap: self.max_gap,
+            min_duration: self.min_duration,
+        }
+    }
+
+    pub fn sampling_params(&self) -> Option<SamplingParams> {
+        self.coarse_interval.map(|coarse| SamplingParams {
+            coarse_interval: Duration::from_secs_f64(coarse),
+            fine_interval: Duration::from_secs(1),
+        })
+    }
+
+    /// How the frames that were scored were picked, as recorded in clip files.
+    pub fn scan_params(&self) -> serde_json::Value {
+        if self.keyframes_only {
+            serde_json::json!({ "mode": "keyframes" })
+        } else if let Some(sampling) = self.sampling_params() {
+            serde_json::json!({ "mode": "adaptive", "sampling": sampling })
+        } else {
+            serde_json::json!({ "mode": "fixed", "fps": SCAN_FPS })
+        }
+    }
+}
+
+#[derive(Parser)]
+pub struct OverrideArgs {
+    pub video_id: String,
+    #[command(subcommand)]
+    pub action: OverrideAction,
+}
+
+#[derive(Parser)]
+pub enum OverrideAction {
+    /// Add a mic test range
+    Add {
+        start: VideoTimestamp,
+        end: VideoTimestamp,
+    },
+    /// Remove everything between two timestamps
+    Remove {
+        start: VideoTimestamp,
+        end: VideoTimestamp,
+    },
+    /// Move the detected boundary closest to FROM to TO
+    Adjust {
+        from: VideoTimestamp,
+        to: VideoTimestamp,
+        /// Seconds a re-detected boundary may drift from FROM and still be adjusted
+        #[clap(long, value_parser = positive_seconds)]
+        tolerance: Option<f64>,
+    },
+    /// Mark the video as having no mic test
+    NoMicTest,
+    /// Keep find-clips from re-detecting the video
+    Lock,
+    Unlock,
+    /// Print the effective ranges
+    Show,
+}
+
+#[derive(Parser)]
+pub struct SyncArgs {
+    #[clap(long)]
+    pub video_id: Option<String>,
+    /// Only show what would change
+    #[clap(long)]
+    pub check: bool,
+    /// Replace destination files whose ranges differ
+    #[clap(long)]
+    pub force: bool,
+}
+
+#[derive(Parser)]
+pub struct DiffArgs {
+    #[clap(default_value = DATASET_CLIPS_DIR)]
+    pub old: String,
+    #[clap(default_value = WORKSPACE_CLIPS_DIR)]
+    pub new: String,
+    /// Smallest boundary shift, in seconds, worth reporting
+    #[clap(long, default_value_t = 0.5, value_parser = positive_seconds)]
+    pub threshold: f64,
+    #[clap(long)]
+    pub json: bool,
+}
+
+#[derive(Parser)]
+pub struct LintArgs {
+    #[clap(long, default_value_t = 3)]
+    pub max_ranges: usize,
+    /// Shortest expected mic test, in seconds
+    #[clap(long, default_value_t = 5.0)]
+    pub min_duration: f64,
+    /// Longest expected mic test, in seconds
+    #[clap(long, default_value_t = 90.0)]
+    pub max_duration: f64,
+    /// Report videos without a mic test published from this date on, by default from the
+    /// first video with one
+    #[clap(long)]
+    pub expect_mictest_since: Option<DateTime<Utc>>,
+    #[clap(long)]
+    pub json: bool,
 }
 
 #[derive(Parser)]
@@ -55,39 +269,99 @@ pub struct MakeClipsArgs {
     pub skip_existing_clips: bool,
     #[clap(long)]
     pub video_id: Option<String>,
+    #[command(flatten)]
+    pub encoder: EncoderArgs,
 }
 
-#[derive(Debug, Deserialize)]
-pub struct PlaylistItemResponse {
-    items: Vec<PlaylistItem>,
+#[derive(Parser)]
+pub struct ConcatArgs {
+    #[command(flatten)]
+    pub encoder: EncoderArgs,
 }
 
-#[derive(Debug, Deserialize)]
-pub struct PlaylistItem {
-    snippet: PlaylistItemSnippet,
-    #[serde(rename = "contentDetails")]
-    content_details: PlaylistItemContentDetails,
+#[derive(Parser)]
+pub struct EncoderArgs {
+    /// Encoder profile: x264, x265, vp9, svt-av1, aom-av1, nvenc, copy or one from
+    /// --encoder-config
+    #[clap(long, default_value = DEFAULT_ENCODER_PROFILE)]
+    pub encoder: String,
+    /// Path to a JSON file of additional encoder profiles by name
+    #[clap(long)]
+    pub encoder_config: Option<String>,
+    /// Profile to use instead when ffmpeg lacks an encoder or hardware acceleration the
+    /// requested one needs
+    #[clap(long)]
+    pub encoder_fallback: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
-pub struct PlaylistItemSnippet {
-    title: String,
+impl EncoderArgs {
+    /// The requested profile, checked against what ffmpeg supports before any work starts.
+    pub fn profile(&self) -> EncoderProfile {
+        let capabilities = capabilities().expect("ffmpeg is not usable");
+        let find = |name: &str| {
+            find_profile(name, self.encoder_config.as_ref())
+                .expect("Failed to load encoder profile")
+        };
+        let profile = find(&self.encoder);
+        let Err(e) = capabilities.check_profile(&profile) else {
+            return profile;
+        };
+        let Some(ref fallback) = self.encoder_fallback else {
+            panic!("Encoder profile {} is not usable: {}", self.encoder, e);
+        };
+        warn!("{}, falling back to encoder profile {}", e, fallback);
+        let profile = find(fallback);
+        if let Err(e) = capabilities.check_profile(&profile) {
+            panic!("Encoder profile {} is not usable either: {}", fallback, e);
+        }
+        profile
+    }
 }
 
-#[derive(Debug, Deserialize)]
-pub struct PlaylistItemContentDetails {
-    #[serde(rename = "videoId")]
-    video_id: String,
-    #[serde(rename = "videoPublishedAt")]
-    video_published_at: DateTime<FixedOffset>,
+fn overrides_file(id: &str) -> String {
+    format!("data/overrides/{}.json", id)
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ClipsInfo {
-    ranges: Vec<(VideoTimestamp, VideoTimestamp)>,
+/// Detected ranges of a video with its manual overrides applied, `None` if it has neither.
+/// Falls back to the published dataset when the video was never analysed locally.
+fn load_effective_clips(id: &str) -> Option<ClipsInfo> {
+    let overrides_file = overrides_file(id);
+    let clips_file = [
+        clips_file(WORKSPACE_CLIPS_DIR, id),
+        clips_file(DATASET_CLIPS_DIR, id),
+    ]
+    .into_iter()
+    .find(|x| x.exists());
+    if clips_file.is_none() && !Path::new(&overrides_file).exists() {
+        return None;
+    }
+    let clips_info = match clips_file {
+        Some(path) => ClipsInfo::load(path).expect("Failed to load clips file"),
+        None => ClipsInfo::new(Vec::new(), None),
+    };
+    let overrides = Overrides::load_or_default(&overrides_file).expect("Failed to load overrides");
+    Some(overrides.apply(&clips_info))
 }
 
-fn find_clips(id: &str, args: &FindClipsArgs) {
+/// Downloads the video unless it is already there, returning its path, or `None` if the video
+/// doesn't need analysing.
+fn fetch_video(entry: &CatalogEntry, args: &FindClipsArgs) -> anyhow::Result<Option<String>> {
+    let id = entry.video_id.as_str();
+    if scheduler().cancel_token().is_cancelled() {
+        return Ok(None);
+    }
+    if Overrides::load_or_default(overrides_file(id))
+        .context("Failed to load overrides")?
+        .locked
+    {
+        info!("{}: locked by overrides, skipping", id);
+        return Ok(None);
+    }
+    let clips_file = format!("data/clips/{}.json", id);
+    if args.skip_existing_clips && Path::new(&clips_file).exists() {
+        return Ok(None);
+    }
+
     let video_path = format!("data/videos/{}", id);
     let video_path = if Path::new(&video_path).exists() {
         video_path
@@ -96,176 +370,333 @@ fn find_clips(id: &str, args: &FindClipsArgs) {
     } else if Path::new(&format!("data/videos/{}.mkv", id)).exists() {
         format!("data/videos/{}.mkv", id)
     } else {
-        println!("Downloading video {}", id);
+        info!("Downloading video {}", id);
         download_video_with_retries(
             &format!("https://www.youtube.com/watch?v={}", id),
             &video_path,
             5,
+            scheduler().cancel_token(),
         )
-        .expect("Failed to download video");
+        .context("Failed to download video")?;
+        if scheduler().dry_run() {
+            info!(
+                "{}: nothing more to plan before the video is downloaded",
+                id
+            );
+            return Ok(None);
+        }
         if Path::new(&format!("data/videos/{}.mp4", id)).exists() {
             format!("data/videos/{}.mp4", id)
         } else if Path::new(&format!("data/videos/{}.mkv", id)).exists() {
             format!("data/videos/{}.mkv", id)
         } else {
-            panic!("Failed to download video")
+            return Err(anyhow::anyhow!("Downloaded video {} not found", id));
         }
     };
+    Ok(Some(video_path))
+}
 
+fn find_clips(entry: &CatalogEntry, video_path: &str, args: &FindClipsArgs) -> anyhow::Result<()> {
+    let id = entry.video_id.as_str();
     let clips_file = format!("data/clips/{}.json", id);
-    if args.skip_existing_clips && Path::new(&clips_file).exists() {
-        return;
-    }
-
-    let second_thumbnail_dir = format!("data/thumbnails/{}/second", id);
-    let second_thumbnail_info = if !std::path::Path::new(&second_thumbnail_dir).exists() {
-        create_dir_all(&second_thumbnail_dir).expect("Failed to create thumbnail directory");
-        generate_thumbnails(&video_path, second_thumbnail_dir.into(), None, None, (1, 1))
-            .expect("Failed to generate thumbnails")
-    } else {
-        collect_thumbnail_into(second_thumbnail_dir.into(), None, (1, 1))
-            .expect("Failed to collect thumbnails")
-    };
 
+    let probe = probe_format(video_path).context("Failed to probe format")?;
+    let fps = probe.video_frame_rate().unwrap_or_else(|| {
+        warn!("{}: no frame rate in probe data, assuming 30 fps", id);
+        (30, 1)
+    });
+    let duration = probe.duration().context("Failed to get duration")?;
     let tp = ThreadPoolBuilder::new()
+        .num_threads(args.classifier_threads)
         .build()
-        .expect("Failed to create thread pool");
+        .context("Failed to create thread pool")?;
 
-    let mictest_thumbnails = Mutex::new(Vec::new());
-    tp.scope(|f| {
-        for thumbnail in &second_thumbnail_info {
-            let mictest_thumbnails = &mictest_thumbnails;
-            f.spawn(move |_| {
-                if image_file_is_mictest(&thumbnail.path).expect("Failed to check image") {
-                    mictest_thumbnails.lock().unwrap().push(thumbnail.clone());
+    let keyframes_file = format!("data/keyframes/{}.json", id);
+    let keyframe_thumbnails = args
+        .keyframes_only
+        .then(|| -> anyhow::Result<_> {
+            let keyframe_thumbnail_dir = format!("data/thumbnails/{}/keyframes", id);
+            if !Path::new(&keyframe_thumbnail_dir).exists() {
+                scheduler()
+                    .create_dir_all(&keyframe_thumbnail_dir)
+                    .context("Failed to create thumbnail directory")?;
+                let generated =
+                    generate_keyframe_thumbnails(video_path, (&keyframe_thumbnail_dir).into());
+                if generated.is_err() {
+                    // an incomplete directory would pass for a finished one next time
+                    let _ = std::fs::remove_dir_all(&keyframe_thumbnail_dir);
+                }
+                let (thumbnails, keyframes) =
+                    generated.context("Failed to generate keyframe thumbnails")?;
+                // a planned extraction leaves the index to the probe of a later run
+                if !scheduler().dry_run() {
+                    keyframes
+                        .save(&keyframes_file)
+                        .context("Failed to save keyframe index")?;
+                }
+                Ok(thumbnails)
+            } else {
+                collect_thumbnail_into(keyframe_thumbnail_dir.into(), (1, 1))
+                    .context("Failed to collect thumbnails")
+            }
+        })
+        .transpose()?;
+    let keyframes = KeyframeIndex::load_or_probe(video_path, &keyframes_file).unwrap_or_else(|e| {
+        warn!("{}: failed to index keyframes, seeking without: {}", id, e);
+        KeyframeIndex::default()
+    });
+    let seeker = FrameSeeker::new(
+        video_path,
+        format!("data/thumbnails/{}/frames-{}-{}", id, fps.0, fps.1).into(),
+        fps,
+        duration,
+    )
+    .with_keyframes(keyframes);
+
+    let scored_thumbnails = match args.sampling_params() {
+        Some(_) if scheduler().dry_run() => {
+            info!(
+                "{}: adaptive sampling picks frames by their scores, nothing more to plan",
+                id
+            );
+            return Ok(());
+        }
+        Some(sampling) => {
+            let scored_thumbnails = tp
+                .install(|| {
+                    adaptive_sample(&duration, sampling, args.hysteresis_params(), |t| {
+                        let thumbnail = seeker.frame(seeker.frame_at(t))?;
+                        let score = image_file_mictest_score(&thumbnail.path)?;
+                        Ok((thumbnail, score))
+                    })
+                })
+                .context("Failed to sample video")?;
+            info!(
+                "{}: scanned {} samples with {} frame decodes, a fixed 1 fps scan takes {}",
+                id,
+                scored_thumbnails.len(),
+                seeker.decodes(),
+                duration.as_float_seconds().ceil()
+            );
+            scored_thumbnails
+        }
+        None => {
+            let second_thumbnail_dir = format!("data/thumbnails/{}/second", id);
+            let second_thumbnail_info = if let Some(thumbnails) = keyframe_thumbnails {
+                thumbnails
+            } else if !std::path::Path::new(&second_thumbnail_dir).exists() {
+                scheduler()
+                    .create_dir_all(&second_thumbnail_dir)
+                    .context("Failed to create thumbnail directory")?;
+                let generated =
+                    generate_thumbnails(video_path, (&second_thumbnail_dir).into(), SCAN_FPS);
+                if generated.is_err() {
+                    let _ = std::fs::remove_dir_all(&second_thumbnail_dir);
+                }
+                generated.context("Failed to generate thumbnails")?
+            } else {
+                collect_thumbnail_into(second_thumbnail_dir.into(), SCAN_FPS)
+                    .context("Failed to collect thumbnails")?
+            };
+            if scheduler().dry_run() {
+                info!("{}: scoring needs the frames, nothing more to plan", id);
+                return Ok(());
+            }
+            let scored_thumbnails = Mutex::new(Vec::new());
+            tp.scope(|f| {
+                for thumbnail in second_thumbnail_info {
+                    let scored_thumbnails = &scored_thumbnails;
+                    f.spawn(move |_| {
+                        let score = image_file_mictest_score(&thumbnail.path);
+                        scored_thumbnails
+                            .lock()
+                            .unwrap()
+                            .push(score.map(|score| (thumbnail, score)));
+                    });
                 }
             });
+            let mut scored_thumbnails = scored_thumbnails
+                .into_inner()
+                .unwrap()
+                .into_iter()
+                .collect::<anyhow::Result<Vec<_>>>()
+                .context("Failed to check image")?;
+            scored_thumbnails.sort_by_key(|(t, _)| t.seq);
+            scored_thumbnails
         }
-    });
-    let mut mictest_thumbnails = mictest_thumbnails.into_inner().unwrap();
-    mictest_thumbnails.sort_by_key(|t| t.seq);
+    };
+    let scan_decodes = seeker.decodes();
 
-    let mictest_ranges =
-        iter_continuous_range(mictest_thumbnails.iter(), |a, b| a.seq + 1 == b.seq)
-            .filter(|(a, b)| b.timestamp.as_float_seconds() - a.timestamp.as_float_seconds() > 4.0);
+    let mictest_ranges = iter_hysteresis_range(
+        scored_thumbnails.iter(),
+        args.hysteresis_params(),
+        |(t, _)| t.timestamp.as_float_seconds(),
+        |(_, score)| *score,
+    )
+    .map(|(a, b)| (a.0.clone(), b.0.clone()));
+    // each boundary lies between its rough sample and the scanned sample next to it, however
+    // far apart the scan samples are
+    let sample_times = scored_thumbnails
+        .iter()
+        .map(|(t, _)| t.timestamp)
+        .collect_vec();
 
+    // a boundary that can't be refined keeps its rough timestamp, unless refining was
+    // cancelled, which would save a half-finished analysis as a finished one
+    let or_rough = |refined: anyhow::Result<RefinedBoundary>, rough, flag| match refined {
+        Err(e) if e.is::<Cancelled>() => Err(e),
+        Err(e) => {
+            warn!("{}: failed to refine mic test boundary: {}", id, e);
+            Ok(RefinedBoundary::rough(rough, flag))
+        }
+        refined => refined,
+    };
     let accurate_mictest_ranges = Mutex::new(Vec::new());
 
     tp.scope(|f| {
         for (begin_rough, end_rough) in mictest_ranges {
-            f.spawn(|_| {
-                let begin_rough = begin_rough.clone();
-                let end_rough = end_rough.clone();
-
-                let begin_range_start = begin_rough.timestamp.add_seconds(-2);
-                let begin_range_end = begin_rough.timestamp.add_seconds(2);
-                let begin_test_thumbnail_dir = format!(
-                    "data/thumbnails/{}/{}-{}",
-                    id,
-                    begin_range_start.as_ffmpeg_arg(),
-                    begin_range_end.as_ffmpeg_arg()
+            let seeker = &seeker;
+            let accurate_mictest_ranges = &accurate_mictest_ranges;
+            let or_rough = &or_rough;
+            let before = sample_times.partition_point(|t| *t < begin_rough.timestamp);
+            let search_from = before
+                .checked_sub(1)
+                .map_or(VideoTimestamp::zero(), |i| sample_times[i]);
+            let after = sample_times.partition_point(|t| *t <= end_rough.timestamp);
+            let search_to = sample_times.get(after).copied().unwrap_or(duration);
+            f.spawn(move |_| {
+                let begin = or_rough(
+                    refine_begin(
+                        seeker,
+                        &begin_rough.timestamp,
+                        &search_from,
+                        image_file_is_mictest,
+                    ),
+                    begin_rough.timestamp,
+                    BoundaryFlag::BeginNotFound,
                 );
-                let begin_thumbnails = if !std::path::Path::new(&begin_test_thumbnail_dir).exists()
-                {
-                    create_dir_all(&begin_test_thumbnail_dir)
-                        .expect("Failed to create thumbnail directory");
-                    generate_thumbnails(
-                        &video_path,
-                        begin_test_thumbnail_dir.into(),
-                        Some(begin_range_start.clone()),
-                        Some(begin_range_end.clone()),
-                        (30, 1),
-                    )
-                    .expect("Failed to generate thumbnails")
-                } else {
-                    collect_thumbnail_into(
-                        begin_test_thumbnail_dir.into(),
-                        Some(begin_range_start.clone()),
-                        (30, 1),
-                    )
-                    .expect("Failed to collect thumbnails")
-                };
-                let begin = begin_thumbnails
-                    .iter()
-                    .find(|t| image_file_is_mictest(&t.path).unwrap())
-                    .unwrap();
-
-                let end_range_start = end_rough.timestamp.add_seconds(-2);
-                let end_range_end = end_rough.timestamp.add_seconds(2);
-                let end_test_thumbnail_dir = format!(
-                    "data/thumbnails/{}/{}-{}",
-                    id,
-                    end_range_start.as_ffmpeg_arg(),
-                    end_range_end.as_ffmpeg_arg()
+                let end = or_rough(
+                    refine_end(
+                        seeker,
+                        &end_rough.timestamp,
+                        &search_to,
+                        image_file_is_mictest,
+                    ),
+                    end_rough.timestamp,
+                    BoundaryFlag::EndNotFound,
                 );
-                let end_thumbnails = if !std::path::Path::new(&end_test_thumbnail_dir).exists() {
-                    create_dir_all(&end_test_thumbnail_dir)
-                        .expect("Failed to create thumbnail directory");
-                    generate_thumbnails(
-                        &video_path,
-                        end_test_thumbnail_dir.into(),
-                        Some(end_range_start.clone()),
-                        Some(end_range_end.clone()),
-                        (30, 1),
-                    )
-                    .expect("Failed to generate thumbnails")
-                } else {
-                    collect_thumbnail_into(
-                        end_test_thumbnail_dir.into(),
-                        Some(end_range_start.clone()),
-                        (30, 1),
-                    )
-                    .expect("Failed to collect thumbnails")
-                };
-                let end = end_thumbnails
-                    .iter()
-                    .tuple_windows()
-                    .find(|(a, b)| {
-                        image_file_is_mictest(&a.path).unwrap()
-                            && !image_file_is_mictest(&b.path).unwrap()
-                    })
-                    .unwrap()
-                    .0;
 
                 accurate_mictest_ranges
                     .lock()
                     .unwrap()
-                    .push((begin.timestamp.clone(), end.timestamp.clone()));
+                    .push(begin.and_then(|begin| Ok((begin, end?))));
             });
         }
     });
-    let mut accurate_mictest_ranges = accurate_mictest_ranges.into_inner().unwrap();
-    accurate_mictest_ranges.sort_by_key(|x| x.0.clone());
-
-    serde_json::to_writer(
-        std::fs::File::create(&clips_file).expect("Failed to create clips file"),
-        &ClipsInfo {
-            ranges: accurate_mictest_ranges.clone(),
-        },
-    )
-    .expect("Failed to write clips file");
-}
-
-fn cmd_find_clips(args: &FindClipsArgs) {
-    let regex_complete = Regex::new("The Complete (.*) Season").unwrap();
-    let mut waiting_for_from = args.from_id.clone();
-    read_dir("urls/aftershow/")
+    let mut refined_ranges = accurate_mictest_ranges
+        .into_inner()
         .unwrap()
         .into_iter()
-        .chain(read_dir("urls/uploads/").unwrap())
-        .map(|url_file| {
-            let url_file = url_file.unwrap();
+        .collect::<anyhow::Result<Vec<_>>>()
+        .context("Failed to refine boundaries")?;
+    refined_ranges.sort_by_key(|(begin, _)| begin.timestamp);
+
+    let detected_ranges = refined_ranges
+        .into_iter()
+        .filter_map(
+            |(begin, end)| match TimeRange::new(begin.timestamp, end.timestamp) {
+                Ok(range) => {
+                    let flags = [begin.flag, end.flag].into_iter().flatten().collect_vec();
+                    for flag in &flags {
+                        warn!("{}: range {} flagged {:?}", id, range, flag);
+                    }
+                    let scores = scored_thumbnails
+                        .iter()
+                        .filter(|(t, _)| range.contains(&t.timestamp))
+                        .map(|(_, score)| *score)
+                        .collect_vec();
+                    let confidence = (!scores.is_empty())
+                        .then(|| scores.iter().sum::<f64>() / scores.len() as f64);
+                    Some(DetectedRange {
+                        range,
+                        confidence,
+                        flags,
+                    })
+                }
+                Err(e) => {
+                    warn!("{}: dropping refined range: {}", id, e);
+                    None
+                }
+            },
+        )
+        .collect_vec();
+    // refined ranges of separate rough ranges may still overlap, keep the first of each
+    let mut accurate_mictest_ranges = IntervalSet::new();
+    let detected_ranges = detected_ranges
+        .into_iter()
+        .filter(|r| {
+            if accurate_mictest_ranges.iter().any(|x| x.overlaps(&r.range)) {
+                warn!("{}: dropping overlapping range {}", id, r.range);
+                return false;
+            }
+            accurate_mictest_ranges.insert(r.range.clone());
+            true
+        })
+        .collect_vec();
+    info!(
+        "{}: refined {} ranges with {} frame decodes",
+        id,
+        accurate_mictest_ranges.len(),
+        seeker.decodes() - scan_decodes
+    );
+    let stats = scheduler().stats();
+    debug!(
+        "ffmpeg: {} running, {} queued, {} completed",
+        stats.running, stats.queued, stats.completed
+    );
 
-            let items = serde_json::from_reader::<_, PlaylistItemResponse>(
-                std::fs::File::open(url_file.path()).unwrap(),
+    for chapter in &entry.mictest_chapter_ranges(Some(duration)) {
+        if !accurate_mictest_ranges.iter().any(|r| r.overlaps(chapter)) {
+            warn!(
+                "{}: chapter mic test {} not covered by any detected range",
+                id, chapter
             );
+        }
+    }
 
-            items.unwrap().items
+    let provenance = Provenance {
+        detector: DETECTOR_NAME.into(),
+        detector_version: DETECTOR_VERSION.into(),
+        parameters: serde_json::json!({
+            "scan": args.scan_params(),
+            "hysteresis": args.hysteresis_params(),
+        }),
+        source: SourceInfo::from_probe(video_path, &probe).context("Failed to describe video")?,
+        analyzed_at: Utc::now(),
+    };
+    // anything cut short by Ctrl-C along the way fell back to rough results
+    scheduler().cancel_token().check()?;
+    ClipsInfo::new(detected_ranges, Some(provenance))
+        .save(&clips_file)
+        .context("Failed to write clips file")
+}
+
+fn cmd_find_clips(args: &FindClipsArgs) -> anyhow::Result<()> {
+    capabilities()
+        .and_then(|c| {
+            ["select", "showinfo"]
+                .iter()
+                .try_for_each(|f| c.check_filter(f))
         })
-        .flatten()
+        .expect("ffmpeg is not usable");
+    let regex_complete = Regex::new("The Complete (.*) Season").unwrap();
+    let mut waiting_for_from = args.from_id.clone();
+    let videos = load_catalog()
+        .expect("Failed to load catalog")
+        .into_iter()
         .filter(|x| match waiting_for_from {
             Some(ref from) => {
-                if &x.content_details.video_id == from {
+                if &x.video_id == from {
                     waiting_for_from = None;
                     true
                 } else {
@@ -275,99 +706,143 @@ fn cmd_find_clips(args: &FindClipsArgs) {
             None => true,
         })
         .filter(|x| {
-            if x.content_details.video_id == "lED1vIbaivA" {
+            if x.video_id == "lED1vIbaivA" {
                 return false;
             }
-            if regex_complete.is_match(&x.snippet.title) {
+            if regex_complete.is_match(&x.title) {
                 return false;
             }
             true
         })
-        .for_each(|video| {
-            info!("Processing {}", video.content_details.video_id);
-            find_clips(&video.content_details.video_id, args);
-        });
+        .collect::<Vec<_>>();
+    run_pipeline(
+        videos,
+        PipelineConfig {
+            prefetch: args.prefetch,
+            fetch_jobs: args.download_jobs,
+            process_jobs: args.jobs,
+        },
+        |video| Ok(fetch_video(&video, args)?.map(|path| (video, path))),
+        |(video, path)| {
+            info!("Processing {}", video.video_id);
+            find_clips(&video, &path, args)
+                .with_context(|| format!("Failed to find clips in {}", video.video_id))
+        },
+    )
+}
+
+fn find_input_video(id: &str) -> Option<String> {
+    [
+        format!("data/videos/{}.mkv", id),
+        format!("data/videos/{}.mp4", id),
+    ]
+    .into_iter()
+    .find(|x| Path::new(x).exists())
+}
+
+fn format_hms(timestamp: VideoTimestamp) -> String {
+    let (hours, minutes, seconds) = timestamp.as_hms();
+    format!("{}:{:02}:{:02}", hours, minutes, seconds)
 }
 
-fn cmd_make_clips(args: &MakeClipsArgs) {
+/// Draws ffmpeg progress towards `total` as a bar with an ETA on a terminal, or logs every
+/// tenth otherwise.
+fn progress_bar(label: String, total: VideoTimestamp) -> impl FnMut(&Progress) + Send {
+    const WIDTH: usize = 30;
+    let terminal = std::io::stderr().is_terminal();
+    let mut logged = 0;
+    move |progress| {
+        let fraction = progress.fraction(total);
+        let eta = progress
+            .eta(total)
+            .map_or("?".to_string(), |eta| format_hms(eta.into()));
+        let speed = progress
+            .speed
+            .map_or("?".to_string(), |speed| format!("{:.2}x", speed));
+        if terminal {
+            let filled = (fraction * WIDTH as f64) as usize;
+            eprint!(
+                "\r{} [{}{}] {:>3.0}% {}/{} {} ETA {}\x1b[K",
+                label,
+                "#".repeat(filled),
+                "-".repeat(WIDTH - filled),
+                fraction * 100.0,
+                format_hms(progress.out_time),
+                format_hms(total),
+                speed,
+                eta
+            );
+            if progress.finished {
+                eprintln!();
+            }
+        } else if progress.finished || (fraction * 10.0) as usize > logged {
+            logged = (fraction * 10.0) as usize;
+            info!(
+                "{}: {:.0}% at {}, ETA {}",
+                label,
+                fraction * 100.0,
+                speed,
+                eta
+            );
+        }
+    }
+}
+
+fn cmd_make_clips(args: &MakeClipsArgs) -> anyhow::Result<()> {
+    let profile = args.encoder.profile();
     let ids = if let Some(ref video_id) = args.video_id {
         vec![video_id.clone()]
     } else {
-        read_dir("urls/aftershow/")
-            .unwrap()
+        load_catalog()
+            .expect("Failed to load catalog")
             .into_iter()
-            .chain(read_dir("urls/uploads/").unwrap())
-            .map(|url_file| {
-                let url_file = url_file.unwrap();
-
-                let items = serde_json::from_reader::<_, PlaylistItemResponse>(
-                    std::fs::File::open(url_file.path()).unwrap(),
-                );
-
-                items.unwrap().items
-            })
-            .flatten()
-            .map(|item| item.content_details.video_id)
+            .map(|entry| entry.video_id)
             .collect::<Vec<_>>()
     };
     for id in ids {
-        let clips_json_file = format!("data/clips/{}.json", id);
-        if !Path::new(&clips_json_file).exists() {
+        let Some(clips_info) = load_effective_clips(&id) else {
             continue;
-        }
+        };
         let clips_mkv_file = format!("data/clips/{}.mkv", id);
-        let clips_info = serde_json::from_reader::<_, ClipsInfo>(
-            std::fs::File::open(&clips_json_file).expect("Failed to open clips file"),
-        )
-        .expect("Failed to parse clips file");
         if clips_info.ranges.is_empty() {
             continue;
         }
-        let input_file = [
-            format!("data/videos/{}.mkv", id),
-            format!("data/videos/{}.mp4", id),
-        ]
-        .into_iter()
-        .find(|x| Path::new(x).exists())
-        .unwrap();
         if !args.skip_existing_clips || !Path::new(&clips_mkv_file).exists() {
+            // ranges can come from the published dataset, without the video ever downloaded
+            let Some(input_file) = find_input_video(&id) else {
+                warn!("{}: no downloaded video to cut, skipping", id);
+                continue;
+            };
+            let ranges = clips_info.intervals();
+            let total = ranges.total_duration().into();
             make_multiple_clip(
                 &input_file,
                 &clips_mkv_file,
-                clips_info
-                    .ranges
-                    .iter()
-                    .sorted_by_key(|x| x.0.clone())
-                    .cloned(),
-                true,
+                &ranges,
+                &profile,
                 true,
+                progress_bar(id.clone(), total),
             )
-            .expect("Failed to make multiple clip");
+            .with_context(|| format!("Failed to make clips of {}", id))?;
         }
     }
+    Ok(())
 }
 
-fn cmd_concat() {
-    let items = read_dir("urls/aftershow/")
-        .unwrap()
+fn cmd_concat(args: &ConcatArgs) -> anyhow::Result<()> {
+    let profile = args.encoder.profile();
+    capabilities()
+        .and_then(|c| c.check_filter("subtitles"))
+        .expect("Cannot burn in titles, ffmpeg needs to be built with libass");
+    let items = load_catalog()
+        .expect("Failed to load catalog")
         .into_iter()
-        .chain(read_dir("urls/uploads/").unwrap())
-        .map(|url_file| {
-            let url_file = url_file.unwrap();
-
-            let items = serde_json::from_reader::<_, PlaylistItemResponse>(
-                std::fs::File::open(url_file.path()).unwrap(),
-            );
-
-            items.unwrap().items
-        })
-        .flatten()
-        .sorted_by_key(|x| x.content_details.video_published_at)
-        .filter_map(|item| {
-            let mkv_path = format!("data/clips/{}.mkv", item.content_details.video_id);
+        .sorted_by_key(|x| x.published_at)
+        .filter_map(|entry| {
+            let mkv_path = format!("data/clips/{}.mkv", entry.video_id);
 
             if Path::new(&mkv_path).exists() {
-                Some((item.snippet.title, mkv_path))
+                Some((entry.title, mkv_path))
             } else {
                 None
             }
@@ -376,36 +851,317 @@ fn cmd_concat() {
 
     info!("Concatenating {} videos", items.len());
 
-    let mut srt_file = std::fs::File::create("data/combined.srt").unwrap();
-    let mut srt_seq = 1;
+    let mut srt = String::new();
     let mut start_ts = VideoTimestamp::zero();
-    for (title, path) in &items {
-        let info = probe_format(&path).expect("Failed to probe format");
-        let duration = info.format.duration.parse().unwrap();
-        let duration = VideoTimestamp::from_float_seconds(duration);
-        let end_ts = start_ts.clone() + duration;
-
-        writeln!(
-            srt_file,
-            "{}\n{} --> {}\n{}\n",
-            srt_seq,
+    for (srt_seq, (title, path)) in items.iter().enumerate() {
+        let info = probe_format(path).context("Failed to probe format")?;
+        let duration = info.duration().context("Failed to get duration")?;
+        let end_ts = start_ts + duration;
+
+        srt.push_str(&format!(
+            "{}\n{} --> {}\n{}\n\n",
+            srt_seq + 1,
             start_ts.as_ffmpeg_arg(),
             end_ts.as_ffmpeg_arg(),
             title,
-        )
-        .unwrap();
+        ));
 
         start_ts = end_ts;
-        srt_seq += 1;
     }
+    scheduler()
+        .write_file("data/combined.srt", &srt)
+        .context("Failed to write subtitles")?;
 
     concat_videos_filter(
         items.into_iter().map(|(_title, path)| path),
         "data/combined.mkv",
         Some("data/combined.srt"),
-        false,
+        &profile,
+        progress_bar("combined".to_string(), start_ts),
+    )
+    .context("Failed to concat videos")
+}
+
+fn cmd_catalog() {
+    let catalog = load_catalog().expect("Failed to load catalog");
+    info!(
+        "{} videos, {} with chapters, {} with mic test chapters",
+        catalog.len(),
+        catalog.iter().filter(|e| !e.chapters.is_empty()).count(),
+        catalog
+            .iter()
+            .filter(|e| e.chapters.iter().any(|c| c.is_mictest()))
+            .count()
+    );
+    if scheduler().dry_run() {
+        return;
+    }
+    serde_json::to_writer_pretty(
+        std::fs::File::create("data/catalog.json").expect("Failed to create catalog file"),
+        &catalog,
+    )
+    .expect("Failed to write catalog file");
+}
+
+fn cmd_override(args: &OverrideArgs) {
+    let path = overrides_file(&args.video_id);
+    let mut overrides = Overrides::load_or_default(&path).expect("Failed to load overrides");
+    let new_range = |start: &VideoTimestamp, end: &VideoTimestamp| {
+        TimeRange::new(*start, *end).expect("Invalid range")
+    };
+    match args.action {
+        OverrideAction::Add { ref start, ref end } => overrides.ops.push(OverrideOp::Add {
+            range: new_range(start, end),
+        }),
+        OverrideAction::Remove { ref start, ref end } => overrides.ops.push(OverrideOp::Remove {
+            range: new_range(start, end),
+        }),
+        OverrideAction::Adjust {
+            from,
+            to,
+            tolerance,
+        } => overrides.ops.push(OverrideOp::Adjust {
+            from,
+            to,
+            tolerance: tolerance.map(Duration::from_secs_f64),
+        }),
+        OverrideAction::NoMicTest => overrides.ops.push(OverrideOp::NoMicTest),
+        OverrideAction::Lock => overrides.locked = true,
+        OverrideAction::Unlock => overrides.locked = false,
+        OverrideAction::Show => {
+            let clips_info = load_effective_clips(&args.video_id)
+                .unwrap_or_else(|| ClipsInfo::new(Vec::new(), None));
+            println!(
+                "{}{}",
+                args.video_id,
+                if overrides.locked { " (locked)" } else { "" }
+            );
+            for range in &clips_info.ranges {
+                println!("  {}", range.range);
+            }
+            return;
+        }
+    }
+    if scheduler().dry_run() {
+        serde_json::to_writer_pretty(std::io::stderr(), &overrides)
+            .expect("Failed to write overrides");
+        return;
+    }
+    overrides.save(&path).expect("Failed to save overrides");
+}
+
+fn sync_all(
+    ids: Vec<String>,
+    load: impl Fn(&str) -> anyhow::Result<Option<ClipsInfo>>,
+    dest_dir: &str,
+    args: &SyncArgs,
+) {
+    let check = args.check || scheduler().dry_run();
+    let (mut changed, mut conflicts, mut invalid) = (0, 0, 0);
+    for id in ids {
+        let outcome = load(&id).and_then(|info| match info {
+            Some(info) => {
+                sync_clips(&info, clips_file(dest_dir, &id), args.force, !check).map(Some)
+            }
+            None => Ok(None),
+        });
+        match outcome {
+            Ok(None | Some(SyncOutcome::Unchanged)) => {}
+            Ok(Some(SyncOutcome::Created)) => {
+                changed += 1;
+                println!("{}: new", id);
+            }
+            Ok(Some(SyncOutcome::Updated(changes))) => {
+                changed += 1;
+                println!("{}:", id);
+                changes.iter().for_each(|c| println!("  {}", c));
+            }
+            Ok(Some(SyncOutcome::Conflict(changes))) => {
+                conflicts += 1;
+                println!("{}: differs, use --force to replace", id);
+                changes.iter().for_each(|c| println!("  {}", c));
+            }
+            Err(e) => {
+                invalid += 1;
+                warn!("{}: {}", id, e);
+            }
+        }
+    }
+    info!(
+        "{} {} in {}, {} conflicts, {} invalid",
+        changed,
+        if check { "to change" } else { "changed" },
+        dest_dir,
+        conflicts,
+        invalid
+    );
+}
+
+fn cmd_publish(args: &SyncArgs) {
+    let ids = match args.video_id {
+        Some(ref id) => vec![id.clone()],
+        None => list_clip_ids(WORKSPACE_CLIPS_DIR).expect("Failed to list workspace clips"),
+    };
+    sync_all(
+        ids,
+        |id| {
+            let path = clips_file(WORKSPACE_CLIPS_DIR, id);
+            if !path.exists() {
+                return Ok(None);
+            }
+            let overrides = Overrides::load_or_default(overrides_file(id))?;
+            Ok(Some(overrides.apply(&ClipsInfo::load(path)?)))
+        },
+        DATASET_CLIPS_DIR,
+        args,
+    );
+}
+
+fn cmd_import(args: &SyncArgs) {
+    let ids = match args.video_id {
+        Some(ref id) => vec![id.clone()],
+        None => list_clip_ids(DATASET_CLIPS_DIR).expect("Failed to list dataset clips"),
+    };
+    sync_all(
+        ids,
+        |id| {
+            let path = clips_file(DATASET_CLIPS_DIR, id);
+            if !path.exists() {
+                return Ok(None);
+            }
+            let dataset = ClipsInfo::load(path)?;
+            // published ranges already have the overrides applied, so they replace the
+            // overridden workspace ranges and the overrides they reflect are dropped
+            let workspace_file = clips_file(WORKSPACE_CLIPS_DIR, id);
+            let overrides = Overrides::load_or_default(overrides_file(id))?;
+            if workspace_file.exists() {
+                let workspace = overrides.apply(&ClipsInfo::load(&workspace_file)?);
+                if workspace.intervals() == dataset.intervals() {
+                    return Ok(None);
+                }
+            }
+            let writes =
+                !(args.check || scheduler().dry_run()) && (args.force || !workspace_file.exists());
+            let remaining = overrides.without_reflected(&dataset.intervals());
+            if writes && remaining.ops.len() < overrides.ops.len() {
+                info!(
+                    "{}: dropping {} overrides the dataset already reflects",
+                    id,
+                    overrides.ops.len() - remaining.ops.len()
+                );
+                remaining.save(overrides_file(id))?;
+            }
+            Ok(Some(dataset))
+        },
+        WORKSPACE_CLIPS_DIR,
+        args,
+    );
+}
+
+fn cmd_diff(args: &DiffArgs) {
+    let diffs = diff_dirs(
+        &args.old,
+        &args.new,
+        Duration::from_secs_f64(args.threshold),
     )
-    .expect("Failed to concat videos");
+    .expect("Failed to diff clips");
+    if args.json {
+        serde_json::to_writer_pretty(std::io::stdout(), &diffs).expect("Failed to write diff");
+        println!();
+        return;
+    }
+    for diff in &diffs {
+        match diff.missing {
+            Some(DiffSide::Old) => println!("{}: only in {}", diff.video_id, args.new),
+            Some(DiffSide::New) => println!("{}: only in {}", diff.video_id, args.old),
+            None => println!("{}:", diff.video_id),
+        }
+        diff.changes.iter().for_each(|c| println!("  {}", c));
+    }
+    info!("{} videos differ", diffs.len());
+}
+
+fn cmd_lint(args: &LintArgs) {
+    let videos = load_catalog()
+        .expect("Failed to load catalog")
+        .into_iter()
+        .filter_map(|entry| load_effective_clips(&entry.video_id).map(|info| (entry, info)))
+        .collect::<Vec<_>>();
+    let config = LintConfig {
+        max_ranges: args.max_ranges,
+        min_duration: Duration::from_secs_f64(args.min_duration),
+        max_duration: Duration::from_secs_f64(args.max_duration),
+        expect_mictest_since: args.expect_mictest_since.or_else(|| {
+            videos
+                .iter()
+                .filter(|(_, info)| !info.ranges.is_empty())
+                .map(|(entry, _)| entry.published_at.with_timezone(&Utc))
+                .min()
+        }),
+        ..LintConfig::default()
+    };
+
+    let mut reports = videos
+        .iter()
+        .map(|(entry, info)| {
+            let duration = if info.is_analysed() {
+                None
+            } else {
+                find_input_video(&entry.video_id).and_then(|path| {
+                    probe_format(&path)
+                        .and_then(|probe| probe.duration())
+                        .map_err(|e| warn!("{}: failed to probe {}: {}", entry.video_id, path, e))
+                        .ok()
+                })
+            };
+            LintReport {
+                video_id: entry.video_id.clone(),
+                findings: lint_clips(
+                    info,
+                    duration,
+                    Some(entry.published_at.with_timezone(&Utc)),
+                    &config,
+                ),
+            }
+        })
+        .filter(|report| !report.findings.is_empty())
+        .collect::<Vec<_>>();
+    prioritize(&mut reports);
+
+    if args.json {
+        serde_json::to_writer_pretty(std::io::stdout(), &reports).expect("Failed to write report");
+        println!();
+        return;
+    }
+    for report in &reports {
+        println!("{}:", report.video_id);
+        for finding in &report.findings {
+            println!("  {:?}: {}", finding.severity(), finding);
+        }
+    }
+    info!("{} of {} videos need review", reports.len(), videos.len());
+}
+
+/// Where `--record-commands` saves what the recorder ran.
+type CommandRecording = Option<(Arc<RecordingRunner>, String)>;
+
+fn save_recorded_commands(recording: &CommandRecording) {
+    if let Some((recorder, path)) = recording {
+        match recorder.save(path) {
+            Ok(()) => info!("Saved the commands run to {}", path),
+            Err(e) => warn!("Failed to save the commands run to {}: {}", path, e),
+        }
+    }
+}
+
+/// Saves the recorded commands however `main` returns, including by panicking, which is when
+/// they are most wanted. `process::exit` skips it, so exits save explicitly first.
+struct SaveRecordedCommands(CommandRecording);
+
+impl Drop for SaveRecordedCommands {
+    fn drop(&mut self) {
+        save_recorded_commands(&self.0);
+    }
 }
 
 fn main() {
@@ -419,9 +1175,82 @@ fn main() {
     */
 
     let cli = Cli::parse();
-    match cli.subcommand {
+    let cancel = CancelToken::new();
+    let recorder = cli.record_commands.as_ref().map(|path| {
+        Arc::new(
+            RecordingRunner::new(Arc::new(SystemRunner))
+                .keep_outputs_in(format!("{}.outputs", path)),
+        )
+    });
+    configure_scheduler(SchedulerConfig {
+        max_processes: cli.ffmpeg_jobs,
+        threads: cli.ffmpeg_threads,
+        log_dir: Some("data/logs".into()),
+        log_successful: cli.ffmpeg_log_all,
+        timeout: cli.ffmpeg_timeout.map(Duration::from_secs),
+        cancel: cancel.clone(),
+        dry_run: cli.dry_run,
+        runner: recorder.clone().map(|r| r as Arc<dyn CommandRunner>),
+    })
+    .expect("Failed to configure ffmpeg scheduler");
+    let recording = recorder.zip(cli.record_commands.clone());
+    let exit_recording = recording.clone();
+    let recording = SaveRecordedCommands(recording);
+    ctrlc::set_handler(move || {
+        if cancel.is_cancelled() {
+            save_recorded_commands(&exit_recording);
+            std::process::exit(130);
+        }
+        warn!("Stopping, press Ctrl-C again to exit immediately");
+        cancel.cancel();
+    })
+    .expect("Failed to set Ctrl-C handler");
+    let result = match cli.subcommand {
         Commands::FindClips(ref args) => cmd_find_clips(args),
         Commands::MakeClips(ref args) => cmd_make_clips(args),
-        Commands::Concat => cmd_concat(),
+        Commands::Concat(ref args) => cmd_concat(args),
+        Commands::Catalog => {
+            cmd_catalog();
+            Ok(())
+        }
+        Commands::Override(ref args) => {
+            cmd_override(args);
+            Ok(())
+        }
+        Commands::Publish(ref args) => {
+            cmd_publish(args);
+            Ok(())
+        }
+        Commands::Import(ref args) => {
+            cmd_import(args);
+            Ok(())
+        }
+        Commands::Diff(ref args) => {
+            cmd_diff(args);
+            Ok(())
+        }
+        Commands::Lint(ref args) => {
+            cmd_lint(args);
+            Ok(())
+        }
+    };
+    if result.is_err() && scheduler().cancel_token().is_cancelled() {
+        // only once every worker has stopped and cleaned up after the processes it killed,
+        // rather than reporting those processes as failures
+        warn!("Cancelled");
+        drop(recording);
+        std::process::exit(130);
+    }
+    result.expect("Command failed");
+
+    if cli.dry_run {
+        let script = shell_script(&scheduler().planned());
+        match cli.plan_script {
+            Some(ref path) => {
+                std::fs::write(path, script).expect("Failed to write plan script");
+                info!("Wrote the planned commands to {}", path);
+            }
+            None => print!("{}", script),
+        }
     }
 }
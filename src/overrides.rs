@@ -0,0 +1,309 @@
+use std::{fs::File, path::Path, time::Duration};
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    clips::{ClipsInfo, DetectedRange},
+    ffmpeg::VideoTimestamp,
+    interval::{IntervalSet, TimeRange},
+};
+
+/// How far a detected boundary may have moved from the one an `Adjust` was written against.
+pub const DEFAULT_ADJUST_TOLERANCE: Duration = Duration::from_secs(1);
+
+/// Manual corrections of one video, stored in `data/overrides/{id}.json` and applied in order
+/// on top of whatever the detector produced.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Overrides {
+    /// Keeps `find-clips` from re-detecting the video.
+    #[serde(default)]
+    pub locked: bool,
+    #[serde(default)]
+    pub ops: Vec<OverrideOp>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum OverrideOp {
+    Add {
+        range: TimeRange,
+    },
+    Remove {
+        range: TimeRange,
+    },
+    /// Moves the range boundary closest to `from` to `to`.
+    Adjust {
+        from: VideoTimestamp,
+        to: VideoTimestamp,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        tolerance: Option<Duration>,
+    },
+    NoMicTest,
+}
+
+impl Overrides {
+    pub fn load<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        Ok(serde_json::from_reader(File::open(path)?)?)
+    }
+
+    /// Loads the overrides at `path`, or empty overrides if there is no such file.
+    pub fn load_or_default<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        if path.as_ref().exists() {
+            Self::load(path)
+        } else {
+            Ok(Self::default())
+        }
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> anyhow::Result<()> {
+        if let Some(parent) = path.as_ref().parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        serde_json::to_writer_pretty(File::create(path)?, self)?;
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        !self.locked && self.ops.is_empty()
+    }
+
+    pub fn apply_to_set(&self, ranges: &IntervalSet) -> IntervalSet {
+        let mut ranges = ranges.clone();
+        for op in &self.ops {
+            ranges = match op {
+                OverrideOp::Add { range } => ranges.union(&IntervalSet::from(vec![range.clone()])),
+                OverrideOp::Remove { range } => {
+                    ranges.subtract(&IntervalSet::from(vec![range.clone()]))
+                }
+                OverrideOp::Adjust {
+                    from,
+                    to,
+                    tolerance,
+                } => adjust_boundary(
+                    &ranges,
+                    from,
+                    to,
+                    tolerance.unwrap_or(DEFAULT_ADJUST_TOLERANCE),
+                ),
+                OverrideOp::NoMicTest => IntervalSet::new(),
+            };
+        }
+        ranges
+    }
+
+    /// The overrides without the ops that would change nothing on `ranges`, because `ranges`
+    /// already went through them, e.g. a published dataset being imported back.
+    pub fn without_reflected(&self, ranges: &IntervalSet) -> Self {
+        Self {
+            locked: self.locked,
+            ops: self
+                .ops
+                .iter()
+                .filter(|op| !op.is_reflected_in(ranges))
+                .cloned()
+                .collect(),
+        }
+    }
+
+    /// Applies the overrides to detector output. Ranges left untouched keep their confidence
+    /// and flags, ranges created or changed by an override lose them.
+    pub fn apply(&self, info: &ClipsInfo) -> ClipsInfo {
+        if self.ops.is_empty() {
+            return info.clone();
+        }
+        let ranges = self
+            .apply_to_set(&info.intervals())
+            .into_iter()
+            .map(|range| {
+                info.ranges
+                    .iter()
+                    .find(|r| r.range == range)
+                    .cloned()
+                    .unwrap_or_else(|| DetectedRange::new(range))
+            })
+            .collect();
+        ClipsInfo {
+            version: info.version,
+            provenance: info.provenance.clone(),
+            ranges,
+        }
+    }
+}
+
+impl OverrideOp {
+    /// Whether applying the op to `ranges` would leave them as they are.
+    pub fn is_reflected_in(&self, ranges: &IntervalSet) -> bool {
+        match self {
+            OverrideOp::Add { range } => {
+                ranges.union(&IntervalSet::from(vec![range.clone()])) == *ranges
+            }
+            OverrideOp::Remove { range } => {
+                ranges.subtract(&IntervalSet::from(vec![range.clone()])) == *ranges
+            }
+            OverrideOp::Adjust {
+                from,
+                to,
+                tolerance,
+            } => {
+                let tolerance = tolerance.unwrap_or(DEFAULT_ADJUST_TOLERANCE);
+                let boundaries = || ranges.iter().flat_map(|r| [r.start(), r.end()]);
+                boundaries().any(|t| t == to)
+                    || !boundaries().any(|t| {
+                        t.micros_since(from).unsigned_abs() <= tolerance.as_micros() as u64
+                    })
+            }
+            OverrideOp::NoMicTest => ranges.is_empty(),
+        }
+    }
+}
+
+fn adjust_boundary(
+    ranges: &IntervalSet,
+    from: &VideoTimestamp,
+    to: &VideoTimestamp,
+    tolerance: Duration,
+) -> IntervalSet {
+    let distance = |t: &VideoTimestamp| Duration::from_micros(t.micros_since(from).unsigned_abs());
+    let nearest = ranges
+        .iter()
+        .enumerate()
+        .flat_map(|(i, r)| [(i, true, r.start()), (i, false, r.end())])
+        .min_by_key(|(_, _, t)| distance(t))
+        .filter(|(_, _, t)| distance(t) <= tolerance);
+
+    let Some((index, is_start, _)) = nearest else {
+        warn!("No range boundary within {:?} of {}", tolerance, from);
+        return ranges.clone();
+    };
+
+    ranges
+        .iter()
+        .enumerate()
+        .filter_map(|(i, r)| {
+            if i != index {
+                return Some(r.clone());
+            }
+            let adjusted = if is_start {
+                TimeRange::new(*to, *r.end())
+            } else {
+                TimeRange::new(*r.start(), *to)
+            };
+            adjusted
+                .map_err(|e| warn!("Dropping range {} adjusted to nothing: {}", r, e))
+                .ok()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(start: u64, end: u64) -> TimeRange {
+        TimeRange::new(
+            VideoTimestamp::from_seconds(start),
+            VideoTimestamp::from_seconds(end),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_apply_overrides() {
+        let detected = ClipsInfo::new(
+            vec![
+                DetectedRange {
+                    confidence: Some(0.9),
+                    ..DetectedRange::new(range(10, 20))
+                },
+                DetectedRange::new(range(100, 130)),
+            ],
+            None,
+        );
+        let overrides = Overrides {
+            locked: false,
+            ops: vec![
+                OverrideOp::Add {
+                    range: range(300, 320),
+                },
+                OverrideOp::Remove {
+                    range: range(120, 200),
+                },
+                OverrideOp::Adjust {
+                    from: VideoTimestamp::from_millis(100_500),
+                    to: VideoTimestamp::from_seconds(95),
+                    tolerance: None,
+                },
+                // nothing near 50 seconds, ignored
+                OverrideOp::Adjust {
+                    from: VideoTimestamp::from_seconds(50),
+                    to: VideoTimestamp::from_seconds(55),
+                    tolerance: None,
+                },
+            ],
+        };
+        let applied = overrides.apply(&detected);
+        assert_eq!(
+            applied.intervals(),
+            IntervalSet::from(vec![range(10, 20), range(95, 120), range(300, 320)])
+        );
+        assert_eq!(applied.ranges[0].confidence, Some(0.9));
+        assert_eq!(applied.ranges[1].confidence, None);
+
+        let none = Overrides {
+            locked: false,
+            ops: vec![OverrideOp::NoMicTest],
+        };
+        assert!(none.apply(&detected).ranges.is_empty());
+    }
+
+    #[test]
+    fn test_publish_import_roundtrip() {
+        let detected = ClipsInfo::new(
+            vec![
+                DetectedRange::new(range(10, 20)),
+                DetectedRange::new(range(100, 130)),
+            ],
+            None,
+        );
+        let overrides = Overrides {
+            locked: true,
+            ops: vec![
+                OverrideOp::Add {
+                    range: range(300, 320),
+                },
+                OverrideOp::Remove {
+                    range: range(120, 200),
+                },
+                OverrideOp::Adjust {
+                    from: VideoTimestamp::from_millis(100_500),
+                    to: VideoTimestamp::from_seconds(95),
+                    tolerance: None,
+                },
+            ],
+        };
+        let published = overrides.apply(&detected).intervals();
+        let imported = overrides.without_reflected(&published);
+        assert!(imported.locked && imported.ops.is_empty());
+
+        // a dataset published before the removal still gets it
+        let older = IntervalSet::from(vec![range(10, 20), range(95, 130), range(300, 320)]);
+        let imported = overrides.without_reflected(&older);
+        assert_eq!(
+            imported.ops,
+            [OverrideOp::Remove {
+                range: range(120, 200)
+            }]
+        );
+        assert_eq!(imported.apply_to_set(&older), published);
+    }
+
+    #[test]
+    fn test_serde() {
+        let json = r#"{"locked":true,"ops":[{"op":"no_mic_test"},{"op":"adjust","from":{"seconds":1,"milliseconds":0},"to":{"seconds":2,"milliseconds":0}}]}"#;
+        let overrides: Overrides = serde_json::from_str(json).unwrap();
+        assert!(overrides.locked);
+        assert_eq!(overrides.ops[0], OverrideOp::NoMicTest);
+        assert_eq!(serde_json::to_string(&overrides).unwrap(), json);
+    }
+}
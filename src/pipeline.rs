@@ -0,0 +1,171 @@
+use std::{
+    collections::VecDeque,
+    sync::{mpsc::sync_channel, Arc, Mutex},
+};
+
+#[derive(Debug, Clone, Copy)]
+pub struct PipelineConfig {
+    /// Items fetched ahead of processing, not counting ones being fetched.
+    pub prefetch: usize,
+    pub fetch_jobs: usize,
+    pub process_jobs: usize,
+}
+
+/// Runs `fetch` and `process` over `items` as two stages, so the next items are fetched, e.g.
+/// downloaded, while earlier ones are processed. Fetching blocks once `prefetch` results are
+/// waiting, and items for which `fetch` returns `None` are skipped.
+///
+/// The first error stops both stages, leaving the remaining items untouched, and is returned
+/// once the items already started are done.
+pub fn run_pipeline<T, U, F, P>(
+    items: Vec<T>,
+    config: PipelineConfig,
+    fetch: F,
+    process: P,
+) -> anyhow::Result<()>
+where
+    T: Send,
+    U: Send,
+    F: Fn(T) -> anyhow::Result<Option<U>> + Sync,
+    P: Fn(U) -> anyhow::Result<()> + Sync,
+{
+    let pending = Mutex::new(items.into_iter().collect::<VecDeque<_>>());
+    let first_error = Mutex::new(None);
+    let fail = |e: anyhow::Error| {
+        pending.lock().unwrap().clear();
+        first_error.lock().unwrap().get_or_insert(e);
+    };
+    let (sender, receiver) = sync_channel(config.prefetch);
+    // only the processors hold the receiver, so once the last of them stops, even by
+    // panicking, fetchers waiting for queue space fail instead of blocking forever
+    let receiver = Arc::new(Mutex::new(receiver));
+
+    std::thread::scope(|s| {
+        for _ in 0..config.fetch_jobs.max(1) {
+            let sender = sender.clone();
+            let (pending, fetch, fail) = (&pending, &fetch, &fail);
+            s.spawn(move || loop {
+                let Some(item) = pending.lock().unwrap().pop_front() else {
+                    break;
+                };
+                match fetch(item) {
+                    Ok(Some(fetched)) => {
+                        if sender.send(fetched).is_err() {
+                            break;
+                        }
+                    }
+                    Ok(None) => {}
+                    Err(e) => {
+                        fail(e);
+                        break;
+                    }
+                }
+            });
+        }
+        // processors stop once every fetcher has dropped its sender
+        drop(sender);
+
+        for _ in 0..config.process_jobs.max(1) {
+            let receiver = receiver.clone();
+            let (first_error, process, fail) = (&first_error, &process, &fail);
+            s.spawn(move || loop {
+                if first_error.lock().unwrap().is_some() {
+                    break;
+                }
+                let next = receiver.lock().unwrap().recv();
+                match next {
+                    Ok(fetched) => {
+                        if let Err(e) = process(fetched) {
+                            fail(e);
+                            break;
+                        }
+                    }
+                    Err(_) => break,
+                }
+            });
+        }
+        drop(receiver);
+    });
+    first_error.into_inner().unwrap().map_or(Ok(()), Err)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+
+    #[test]
+    fn test_pipeline_bounds_prefetch() {
+        let config = PipelineConfig {
+            prefetch: 2,
+            fetch_jobs: 3,
+            process_jobs: 2,
+        };
+        let fetched = AtomicUsize::new(0);
+        let processed = Mutex::new(Vec::new());
+        run_pipeline(
+            (0..50).collect(),
+            config,
+            |i| {
+                if i % 5 == 0 {
+                    return Ok(None);
+                }
+                // fetched results are queued, being processed, or waiting for queue space
+                let ahead =
+                    fetched.fetch_add(1, Ordering::SeqCst) + 1 - processed.lock().unwrap().len();
+                assert!(ahead <= config.prefetch + config.fetch_jobs + config.process_jobs);
+                Ok(Some(i))
+            },
+            |i| {
+                std::thread::sleep(std::time::Duration::from_millis(1));
+                processed.lock().unwrap().push(i);
+                Ok(())
+            },
+        )
+        .unwrap();
+        let mut processed = processed.into_inner().unwrap();
+        processed.sort();
+        assert_eq!(
+            processed,
+            (0..50).filter(|i| i % 5 != 0).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_pipeline_stops() {
+        let config = PipelineConfig {
+            prefetch: 1,
+            fetch_jobs: 2,
+            process_jobs: 1,
+        };
+        let (fetched, processed) = (AtomicUsize::new(0), AtomicUsize::new(0));
+        let result = run_pipeline(
+            (0..50).collect(),
+            config,
+            |i| {
+                fetched.fetch_add(1, Ordering::SeqCst);
+                Ok(Some(i))
+            },
+            // by count rather than by item, since fetchers may hand items over out of order
+            |_: i32| match processed.fetch_add(1, Ordering::SeqCst) {
+                2 => Err(anyhow::anyhow!("failed on the third item")),
+                _ => Ok(()),
+            },
+        );
+        assert_eq!(result.unwrap_err().to_string(), "failed on the third item");
+        assert_eq!(processed.load(Ordering::SeqCst), 3);
+        assert!(fetched.load(Ordering::SeqCst) < 10);
+
+        // a panicking processor must not leave fetchers blocked on the full queue
+        let result = std::panic::catch_unwind(|| {
+            run_pipeline(
+                (0..50).collect(),
+                config,
+                |i| Ok(Some(i)),
+                |_: i32| -> anyhow::Result<()> { panic!("processor panicked") },
+            )
+        });
+        assert!(result.is_err());
+    }
+}
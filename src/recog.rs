@@ -1,15 +1,34 @@
 use image::{DynamicImage, GenericImageView, Pixel};
 use log::debug;
 
+pub const DETECTOR_NAME: &str = "black-white-frame";
+pub const DETECTOR_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+pub const MICTEST_SCORE_THRESHOLD: f64 = 0.5;
+
 pub fn image_file_is_mictest(path: &str) -> anyhow::Result<bool> {
     let img = image::open(path)?;
     Ok(image_is_mictest(img))
 }
 
+pub fn image_file_mictest_score(path: &str) -> anyhow::Result<f64> {
+    let img = image::open(path)?;
+    Ok(image_mictest_score(&img))
+}
+
 pub fn image_is_mictest(img: DynamicImage) -> bool {
+    image_mictest_score(&img) >= MICTEST_SCORE_THRESHOLD
+}
+
+/// Scores how much a frame looks like a mic test, from 0.0 (colorful or all black) to 1.0
+/// (strictly black, white and grey). A frame with 1% colored pixels scores exactly
+/// [`MICTEST_SCORE_THRESHOLD`].
+pub fn image_mictest_score(img: &DynamicImage) -> f64 {
+    let limit = (img.width() * img.height() / 100).max(1) * 2;
     let mut non_black_white_pixels = 0;
+    let mut any_non_black = false;
 
-    img.pixels().all(|(_x, _y, rgba)| {
+    for (_x, _y, rgba) in img.pixels() {
         let c = rgba.channels();
         let r = c[0];
         let g = c[1];
@@ -17,22 +36,19 @@ pub fn image_is_mictest(img: DynamicImage) -> bool {
         let is_black = r < 20 && g < 20 && b < 20;
         let is_white = r > 220 && g > 220 && b > 220;
         let is_grey = r == g && g == b;
+        any_non_black |= !is_black;
         if !is_black && !is_white && !is_grey {
             non_black_white_pixels += 1;
-            if non_black_white_pixels > img.width() * img.height() / 100 {
+            if non_black_white_pixels >= limit {
                 debug!("rejecting image because it has too many non-black/white pixels");
-                return false;
+                return 0.0;
             }
         }
+    }
 
-        return true;
-    }) && img.pixels().any(|(_x, _y, rgba)| {
-        let c = rgba.channels();
-        let r = c[0];
-        let g = c[1];
-        let b = c[2];
+    if !any_non_black {
+        return 0.0;
+    }
 
-        let is_black = r < 20 && g < 20 && b < 20;
-        !is_black
-    })
+    1.0 - non_black_white_pixels as f64 / limit as f64
 }
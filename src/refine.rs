@@ -0,0 +1,338 @@
+use std::{
+    fs::create_dir_all,
+    path::PathBuf,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::ffmpeg::{
+    keyframes::KeyframeIndex,
+    thumbnail::{extract_frame, Thumbnail},
+    VideoTimestamp,
+};
+
+/// Extracts single frames of a video on demand, caching them as `frame{n}.jpg` next to their
+/// presentation timestamp in `frame{n}.json`.
+pub struct FrameSeeker<'a> {
+    input: &'a str,
+    cache_dir: PathBuf,
+    fps: (u64, u64),
+    duration: VideoTimestamp,
+    keyframes: Option<KeyframeIndex>,
+    decodes: AtomicUsize,
+    partial_seq: AtomicUsize,
+}
+
+impl<'a> FrameSeeker<'a> {
+    pub fn new(
+        input: &'a str,
+        cache_dir: PathBuf,
+        fps: (u64, u64),
+        duration: VideoTimestamp,
+    ) -> Self {
+        Self {
+            input,
+            cache_dir,
+            fps,
+            duration,
+            keyframes: None,
+            decodes: AtomicUsize::new(0),
+            partial_seq: AtomicUsize::new(0),
+        }
+    }
+
+    /// Seeks to known keyframes instead of leaving it to ffmpeg to find them.
+    pub fn with_keyframes(mut self, keyframes: KeyframeIndex) -> Self {
+        self.keyframes = (!keyframes.is_empty()).then_some(keyframes);
+        self
+    }
+
+    pub fn fps(&self) -> (u64, u64) {
+        self.fps
+    }
+
+    /// Number of frames decoded so far, not counting cache hits.
+    pub fn decodes(&self) -> usize {
+        self.decodes.load(Ordering::Relaxed)
+    }
+
+    pub fn duration(&self) -> &VideoTimestamp {
+        &self.duration
+    }
+
+    pub fn last_frame(&self) -> u64 {
+        self.frame_at(&self.duration).saturating_sub(1)
+    }
+
+    pub fn frame_at(&self, timestamp: &VideoTimestamp) -> u64 {
+        timestamp.as_frame(self.fps)
+    }
+
+    /// Extracts `frame` unless cached, returning it with its presentation timestamp. Frames are
+    /// written under a temporary name and renamed into place, the image before its timestamp, so
+    /// parallel bisects extracting the same frame never read half-written files.
+    pub fn frame(&self, frame: u64) -> anyhow::Result<Thumbnail> {
+        let path = self.cache_dir.join(format!("frame{}.jpg", frame));
+        let pts_path = self.cache_dir.join(format!("frame{}.json", frame));
+        let timestamp = if path.exists() && pts_path.exists() {
+            serde_json::from_reader(std::fs::File::open(&pts_path)?)?
+        } else {
+            create_dir_all(&self.cache_dir)?;
+            // seek half a frame early so rounding never lands on the next frame
+            let half_frame = VideoTimestamp::from_frame(1, self.fps).as_micros() as i64 / 2;
+            let seek =
+                VideoTimestamp::from_frame(frame, self.fps).saturating_add_micros(-half_frame);
+            let seq = self.partial_seq.fetch_add(1, Ordering::Relaxed);
+            let partial = |ext| {
+                self.cache_dir
+                    .join(format!(".frame{}.{}.partial.{}", frame, seq, ext))
+            };
+            let (partial_path, partial_pts_path) = (partial("jpg"), partial("json"));
+            let timestamp =
+                extract_frame(self.input, &seek, &partial_path, self.keyframes.as_ref())?;
+            self.decodes.fetch_add(1, Ordering::Relaxed);
+            serde_json::to_writer(std::fs::File::create(&partial_pts_path)?, &timestamp)?;
+            std::fs::rename(&partial_path, &path)?;
+            std::fs::rename(&partial_pts_path, &pts_path)?;
+            timestamp
+        };
+        Ok(Thumbnail {
+            seq: frame,
+            path: path.to_str().unwrap().into(),
+            timestamp,
+        })
+    }
+}
+
+/// Finds the frame next to a transition by bisecting between a frame known to be `positive`
+/// and one known to be negative. Returns the positive frame adjacent to the transition,
+/// whichever side of `positive` the negative frame lies on.
+pub fn bisect_boundary<F>(positive: u64, negative: u64, mut is_positive: F) -> anyhow::Result<u64>
+where
+    F: FnMut(u64) -> anyhow::Result<bool>,
+{
+    let (mut positive, mut negative) = (positive, negative);
+    while positive.abs_diff(negative) > 1 {
+        let mid = positive.min(negative) + positive.abs_diff(negative) / 2;
+        if is_positive(mid)? {
+            positive = mid;
+        } else {
+            negative = mid;
+        }
+    }
+    Ok(positive)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BoundaryFlag {
+    /// The mic test was already running on the first frame of the video.
+    BeginAtVideoStart,
+    /// The mic test was still running on the last frame of the video.
+    EndAtVideoEnd,
+    /// No transition into the mic test was found, the rough begin was kept.
+    BeginNotFound,
+    /// No transition out of the mic test was found, the rough end was kept.
+    EndNotFound,
+}
+
+#[derive(Debug, Clone)]
+pub struct RefinedBoundary {
+    pub timestamp: VideoTimestamp,
+    pub flag: Option<BoundaryFlag>,
+}
+
+impl RefinedBoundary {
+    pub fn rough(timestamp: VideoTimestamp, flag: BoundaryFlag) -> Self {
+        Self {
+            timestamp,
+            flag: Some(flag),
+        }
+    }
+}
+
+/// Bisects between `positive` and `negative`, where `negative` is the first or last frame of
+/// the video if `at_edge` is set. A search window without a transition resolves to the edge
+/// frame when it reaches the edge, and keeps the rough `positive` frame flagged `missing`
+/// otherwise.
+fn refine_between<F>(
+    seeker: &FrameSeeker,
+    positive: u64,
+    negative: u64,
+    at_edge: (bool, BoundaryFlag),
+    missing: BoundaryFlag,
+    classify: F,
+) -> anyhow::Result<RefinedBoundary>
+where
+    F: Fn(&str) -> anyhow::Result<bool>,
+{
+    let is_positive = |frame| classify(&seeker.frame(frame)?.path);
+    if !is_positive(positive)? {
+        return Ok(RefinedBoundary::rough(
+            seeker.frame(positive)?.timestamp,
+            missing,
+        ));
+    }
+    if positive == negative || is_positive(negative)? {
+        let (frame, flag) = if at_edge.0 {
+            (negative, at_edge.1)
+        } else {
+            (positive, missing)
+        };
+        return Ok(RefinedBoundary::rough(seeker.frame(frame)?.timestamp, flag));
+    }
+    let frame = bisect_boundary(positive, negative, is_positive)?;
+    Ok(RefinedBoundary {
+        timestamp: seeker.frame(frame)?.timestamp,
+        flag: None,
+    })
+}
+
+/// Refines a rough mic test start to the first mic test frame, searching back to `from`, the
+/// last scanned time before `rough` that was not a mic test.
+pub fn refine_begin<F>(
+    seeker: &FrameSeeker,
+    rough: &VideoTimestamp,
+    from: &VideoTimestamp,
+    classify: F,
+) -> anyhow::Result<RefinedBoundary>
+where
+    F: Fn(&str) -> anyhow::Result<bool>,
+{
+    let positive = seeker.frame_at(rough).min(seeker.last_frame());
+    let negative = seeker.frame_at(from).min(positive);
+    refine_between(
+        seeker,
+        positive,
+        negative,
+        (negative == 0, BoundaryFlag::BeginAtVideoStart),
+        BoundaryFlag::BeginNotFound,
+        classify,
+    )
+}
+
+/// Refines a rough mic test end to the last mic test frame, searching up to `to`, the first
+/// scanned time after `rough` that was not a mic test.
+pub fn refine_end<F>(
+    seeker: &FrameSeeker,
+    rough: &VideoTimestamp,
+    to: &VideoTimestamp,
+    classify: F,
+) -> anyhow::Result<RefinedBoundary>
+where
+    F: Fn(&str) -> anyhow::Result<bool>,
+{
+    let last_frame = seeker.last_frame();
+    let positive = seeker.frame_at(rough).min(last_frame);
+    let negative = seeker.frame_at(to).clamp(positive, last_frame);
+    refine_between(
+        seeker,
+        positive,
+        negative,
+        (negative == last_frame, BoundaryFlag::EndAtVideoEnd),
+        BoundaryFlag::EndNotFound,
+        classify,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bisect_boundary() {
+        let mut probes = 0;
+        let begin = bisect_boundary(1000, 880, |f| {
+            probes += 1;
+            Ok(f >= 937)
+        })
+        .unwrap();
+        assert_eq!(begin, 937);
+        assert!(probes <= 7);
+
+        let end = bisect_boundary(500, 620, |f| Ok(f <= 555)).unwrap();
+        assert_eq!(end, 555);
+
+        assert_eq!(bisect_boundary(10, 11, |_| unreachable!()).unwrap(), 10);
+    }
+
+    /// A 10 s video at 10 fps whose frames are all cached, so nothing is extracted.
+    fn cached_seeker(name: &str) -> FrameSeeker<'static> {
+        let dir = std::env::temp_dir().join(format!("refine-test-{}-{}", name, std::process::id()));
+        create_dir_all(&dir).unwrap();
+        for frame in 0..100 {
+            std::fs::File::create(dir.join(format!("frame{}.jpg", frame))).unwrap();
+            let pts = VideoTimestamp::from_frame(frame, (10, 1));
+            serde_json::to_writer(
+                std::fs::File::create(dir.join(format!("frame{}.json", frame))).unwrap(),
+                &pts,
+            )
+            .unwrap();
+        }
+        FrameSeeker::new("video.mp4", dir, (10, 1), VideoTimestamp::from_seconds(10))
+    }
+
+    /// Classifies the cached frames as positive inside `frames`.
+    fn classify(frames: std::ops::RangeInclusive<u64>) -> impl Fn(&str) -> anyhow::Result<bool> {
+        move |path| {
+            let name = std::path::Path::new(path)
+                .file_stem()
+                .unwrap()
+                .to_str()
+                .unwrap();
+            Ok(frames.contains(&name["frame".len()..].parse()?))
+        }
+    }
+
+    #[test]
+    fn test_refine_edges() {
+        let seeker = cached_seeker("edges");
+        let ts = |frame| VideoTimestamp::from_frame(frame, (10, 1));
+
+        let begin = refine_begin(&seeker, &ts(50), &ts(30), classify(37..=80)).unwrap();
+        assert_eq!((begin.timestamp, begin.flag), (ts(37), None));
+        let end = refine_end(&seeker, &ts(70), &ts(90), classify(37..=80)).unwrap();
+        assert_eq!((end.timestamp, end.flag), (ts(80), None));
+
+        // keyframes many seconds apart, the transition is far from the rough boundary
+        let begin = refine_begin(&seeker, &ts(90), &ts(10), classify(37..=95)).unwrap();
+        assert_eq!((begin.timestamp, begin.flag), (ts(37), None));
+        let end = refine_end(&seeker, &ts(40), &ts(99), classify(37..=95)).unwrap();
+        assert_eq!((end.timestamp, end.flag), (ts(95), None));
+
+        // the search window reaches the first and last frame
+        let begin = refine_begin(&seeker, &ts(10), &ts(0), classify(0..=30)).unwrap();
+        assert_eq!(
+            (begin.timestamp, begin.flag),
+            (ts(0), Some(BoundaryFlag::BeginAtVideoStart))
+        );
+        let end = refine_end(
+            &seeker,
+            &ts(95),
+            &VideoTimestamp::from_seconds(10),
+            classify(70..=99),
+        )
+        .unwrap();
+        assert_eq!(
+            (end.timestamp, end.flag),
+            (ts(99), Some(BoundaryFlag::EndAtVideoEnd))
+        );
+
+        // no transition within the window, or a rough boundary that isn't a mic test at all
+        let begin = refine_begin(&seeker, &ts(50), &ts(30), classify(0..=99)).unwrap();
+        assert_eq!(
+            (begin.timestamp, begin.flag),
+            (ts(50), Some(BoundaryFlag::BeginNotFound))
+        );
+        let end = refine_end(&seeker, &ts(50), &ts(70), classify(0..=99)).unwrap();
+        assert_eq!(
+            (end.timestamp, end.flag),
+            (ts(50), Some(BoundaryFlag::EndNotFound))
+        );
+        let begin = refine_begin(&seeker, &ts(50), &ts(30), classify(60..=70)).unwrap();
+        assert_eq!(begin.flag, Some(BoundaryFlag::BeginNotFound));
+        assert_eq!(seeker.decodes(), 0);
+        std::fs::remove_dir_all(&seeker.cache_dir).unwrap();
+    }
+}
@@ -0,0 +1,526 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+    fs::File,
+    os::unix::process::ExitStatusExt,
+    path::{Path, PathBuf},
+    process::{Command, ExitStatus, Stdio},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, OnceLock,
+    },
+    time::Duration,
+};
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cancel::CancelToken,
+    ffmpeg::process::{command_line, for_each_line, wait_child, Stop},
+};
+
+/// Wait status of a process killed with SIGKILL, what replays of killed processes report.
+const SIGKILL: i32 = 9;
+
+/// How a command run by a [`CommandRunner`] ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Outcome {
+    pub status: ExitStatus,
+    /// Set if the runner killed the process.
+    pub stop: Option<Stop>,
+}
+
+/// Runs external commands, so what calls ffmpeg, ffprobe or the downloader can be tested
+/// with recorded or made up output instead of the real programs.
+pub trait CommandRunner: fmt::Debug + Send + Sync {
+    /// Runs `cmd` to completion, passing every line of its stdout and stderr on as it comes.
+    /// The process is killed once `cancel` is cancelled or after `timeout`.
+    fn run(
+        &self,
+        cmd: &mut Command,
+        on_stdout: &mut (dyn FnMut(&str) + Send),
+        on_stderr: &mut dyn FnMut(&str),
+        cancel: &CancelToken,
+        timeout: Option<Duration>,
+    ) -> anyhow::Result<Outcome>;
+
+    /// Like [`run`](Self::run) for commands whose output is meant for the terminal as it is,
+    /// such as progress bars redrawn with carriage returns. Runners without a terminal to hand
+    /// it to print it line by line.
+    fn run_inherited(
+        &self,
+        cmd: &mut Command,
+        cancel: &CancelToken,
+        timeout: Option<Duration>,
+    ) -> anyhow::Result<Outcome> {
+        self.run(
+            cmd,
+            &mut |line| println!("{}", line),
+            &mut |line| eprintln!("{}", line),
+            cancel,
+            timeout,
+        )
+    }
+}
+
+/// Runs commands as processes.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemRunner;
+
+impl CommandRunner for SystemRunner {
+    fn run(
+        &self,
+        cmd: &mut Command,
+        on_stdout: &mut (dyn FnMut(&str) + Send),
+        on_stderr: &mut dyn FnMut(&str),
+        cancel: &CancelToken,
+        timeout: Option<Duration>,
+    ) -> anyhow::Result<Outcome> {
+        cmd.stdin(Stdio::null());
+        cmd.stdout(Stdio::piped());
+        cmd.stderr(Stdio::piped());
+        let mut child = cmd
+            .spawn()
+            .map_err(|e| anyhow::anyhow!("Failed to start {}: {}", command_line(cmd), e))?;
+        let stdout = child.stdout.take().unwrap();
+        let stderr = child.stderr.take().unwrap();
+        let (status, stop) = std::thread::scope(|s| {
+            let stdout = s.spawn(move || for_each_line(stdout, on_stdout));
+            let waiter = s.spawn(|| wait_child(&mut child, cancel, timeout));
+            for_each_line(stderr, on_stderr)?;
+            stdout.join().unwrap()?;
+            waiter.join().unwrap()
+        })?;
+        Ok(Outcome { status, stop })
+    }
+
+    fn run_inherited(
+        &self,
+        cmd: &mut Command,
+        cancel: &CancelToken,
+        timeout: Option<Duration>,
+    ) -> anyhow::Result<Outcome> {
+        cmd.stdin(Stdio::null());
+        cmd.stdout(Stdio::inherit());
+        cmd.stderr(Stdio::inherit());
+        let mut child = cmd
+            .spawn()
+            .map_err(|e| anyhow::anyhow!("Failed to start {}: {}", command_line(cmd), e))?;
+        let (status, stop) = wait_child(&mut child, cancel, timeout)?;
+        Ok(Outcome { status, stop })
+    }
+}
+
+/// A command and what it printed, as saved by [`RecordingRunner`] and played back by
+/// [`ReplayRunner`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Recording {
+    pub command: String,
+    #[serde(default)]
+    pub stdout: Vec<String>,
+    #[serde(default)]
+    pub stderr: Vec<String>,
+    /// Exit code, `None` if the process was killed by a signal.
+    pub code: Option<i32>,
+    /// Files the command writes, recreated on replay so later steps find them.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub outputs: Vec<RecordedFile>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordedFile {
+    pub path: PathBuf,
+    /// Copy of what the command wrote, replayed as is. The file is created empty without one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub saved: Option<PathBuf>,
+}
+
+impl Recording {
+    /// A command that printed `stdout` and exited successfully.
+    pub fn success<S: ToString>(command: &str, stdout: &[S]) -> Self {
+        Self {
+            command: command.to_string(),
+            stdout: stdout.iter().map(|line| line.to_string()).collect(),
+            stderr: Vec::new(),
+            code: Some(0),
+            outputs: Vec::new(),
+        }
+    }
+
+    pub fn with_outputs<P: AsRef<Path>>(mut self, outputs: &[P]) -> Self {
+        self.outputs = outputs
+            .iter()
+            .map(|p| RecordedFile {
+                path: p.as_ref().to_path_buf(),
+                saved: None,
+            })
+            .collect();
+        self
+    }
+}
+
+/// Runs commands with another runner and keeps what they printed.
+#[derive(Debug)]
+pub struct RecordingRunner {
+    inner: Arc<dyn CommandRunner>,
+    recordings: Mutex<Vec<Recording>>,
+    keep_outputs: Option<PathBuf>,
+    kept: AtomicUsize,
+}
+
+impl RecordingRunner {
+    pub fn new(inner: Arc<dyn CommandRunner>) -> Self {
+        Self {
+            inner,
+            recordings: Mutex::new(Vec::new()),
+            keep_outputs: None,
+            kept: AtomicUsize::new(0),
+        }
+    }
+
+    /// Keeps a copy of every file the commands write in `dir`, hard linked where possible so
+    /// whole videos aren't copied, for replays to write them again as they were.
+    pub fn keep_outputs_in<P: AsRef<Path>>(mut self, dir: P) -> Self {
+        self.keep_outputs = Some(dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// Every command run so far, in the order they finished.
+    pub fn recordings(&self) -> Vec<Recording> {
+        self.recordings.lock().unwrap().clone()
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> anyhow::Result<()> {
+        serde_json::to_writer_pretty(File::create(path)?, &self.recordings())?;
+        Ok(())
+    }
+
+    /// Runs `cmd` with `run` and records it along with the files it wrote, meaning files
+    /// named by its arguments that it created or rewrote, which a replay has to write again for
+    /// whatever reads or renames them next.
+    fn record<F>(&self, cmd: &mut Command, run: F) -> anyhow::Result<Outcome>
+    where
+        F: FnOnce(&mut Command, &mut Vec<String>, &mut Vec<String>) -> anyhow::Result<Outcome>,
+    {
+        let modified = |path: &Path| path.metadata().and_then(|m| m.modified()).ok();
+        let candidates = |cmd: &Command| {
+            cmd.get_args()
+                .flat_map(|arg| output_candidates(Path::new(arg)))
+                .collect::<Vec<_>>()
+        };
+        let before = candidates(cmd)
+            .into_iter()
+            .map(|path| {
+                let before = modified(&path);
+                (path, before)
+            })
+            .collect::<HashMap<_, _>>();
+        let (mut stdout, mut stderr) = (Vec::new(), Vec::new());
+        let outcome = run(cmd, &mut stdout, &mut stderr)?;
+        let outputs = candidates(cmd)
+            .into_iter()
+            .filter(|path| path.is_file() && modified(path) != before.get(path).copied().flatten())
+            .map(|path| {
+                let saved = self.keep(&path)?;
+                Ok(RecordedFile { path, saved })
+            })
+            .collect::<std::io::Result<Vec<_>>>()?;
+        self.recordings.lock().unwrap().push(Recording {
+            command: command_line(cmd),
+            stdout,
+            stderr,
+            code: outcome.status.code(),
+            outputs,
+        });
+        Ok(outcome)
+    }
+
+    fn keep(&self, path: &Path) -> std::io::Result<Option<PathBuf>> {
+        let Some(ref dir) = self.keep_outputs else {
+            return Ok(None);
+        };
+        std::fs::create_dir_all(dir)?;
+        let kept = dir.join(format!(
+            "{}-{}",
+            self.kept.fetch_add(1, Ordering::SeqCst),
+            path.file_name().unwrap_or_default().to_string_lossy()
+        ));
+        if std::fs::hard_link(path, &kept).is_err() {
+            std::fs::copy(path, &kept)?;
+        }
+        Ok(Some(kept))
+    }
+}
+
+impl CommandRunner for RecordingRunner {
+    fn run(
+        &self,
+        cmd: &mut Command,
+        on_stdout: &mut (dyn FnMut(&str) + Send),
+        on_stderr: &mut dyn FnMut(&str),
+        cancel: &CancelToken,
+        timeout: Option<Duration>,
+    ) -> anyhow::Result<Outcome> {
+        self.record(cmd, |cmd, stdout, stderr| {
+            self.inner.run(
+                cmd,
+                &mut |line| {
+                    on_stdout(line);
+                    stdout.push(line.to_string());
+                },
+                &mut |line| {
+                    on_stderr(line);
+                    stderr.push(line.to_string());
+                },
+                cancel,
+                timeout,
+            )
+        })
+    }
+
+    /// The output goes straight to the terminal, so only the exit code and outputs are kept.
+    fn run_inherited(
+        &self,
+        cmd: &mut Command,
+        cancel: &CancelToken,
+        timeout: Option<Duration>,
+    ) -> anyhow::Result<Outcome> {
+        self.record(cmd, |cmd, _, _| {
+            self.inner.run_inherited(cmd, cancel, timeout)
+        })
+    }
+}
+
+/// Files `arg` may name: itself, or every file in its directory an image sequence pattern such
+/// as `thumb%04d.jpg` matches.
+fn output_candidates(arg: &Path) -> Vec<PathBuf> {
+    static SEQUENCE: OnceLock<Regex> = OnceLock::new();
+    let sequence = SEQUENCE.get_or_init(|| Regex::new(r"%0?\d*d").unwrap());
+    let Some(name) = arg.file_name().and_then(|name| name.to_str()) else {
+        return vec![arg.to_path_buf()];
+    };
+    let Some(number) = sequence.find(name) else {
+        return vec![arg.to_path_buf()];
+    };
+    let expanded = Regex::new(&format!(
+        r"^{}\d+{}$",
+        regex::escape(&name[..number.start()]),
+        regex::escape(&name[number.end()..])
+    ))
+    .unwrap();
+    let dir = match arg.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    std::fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| entry.file_name())
+        .filter(|file| file.to_str().is_some_and(|file| expanded.is_match(file)))
+        .map(|file| arg.with_file_name(file))
+        .collect()
+}
+
+/// Plays back recordings instead of running anything. Recordings are matched by command
+/// line, and ones for the same command are used in the order they were added.
+#[derive(Debug, Default)]
+pub struct ReplayRunner {
+    recordings: Mutex<HashMap<String, VecDeque<Recording>>>,
+}
+
+impl ReplayRunner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        let replay = Self::new();
+        let recordings: Vec<Recording> = serde_json::from_reader(File::open(path)?)?;
+        recordings.into_iter().for_each(|r| replay.push(r));
+        Ok(replay)
+    }
+
+    pub fn push(&self, recording: Recording) {
+        self.recordings
+            .lock()
+            .unwrap()
+            .entry(recording.command.clone())
+            .or_default()
+            .push_back(recording);
+    }
+
+    /// Whether every recording was played back.
+    pub fn is_exhausted(&self) -> bool {
+        self.recordings
+            .lock()
+            .unwrap()
+            .values()
+            .all(|r| r.is_empty())
+    }
+}
+
+impl CommandRunner for ReplayRunner {
+    fn run(
+        &self,
+        cmd: &mut Command,
+        on_stdout: &mut (dyn FnMut(&str) + Send),
+        on_stderr: &mut dyn FnMut(&str),
+        cancel: &CancelToken,
+        _timeout: Option<Duration>,
+    ) -> anyhow::Result<Outcome> {
+        let command = command_line(cmd);
+        let recording = self
+            .recordings
+            .lock()
+            .unwrap()
+            .get_mut(&command)
+            .and_then(|r| r.pop_front())
+            .ok_or_else(|| anyhow::anyhow!("No recording of {}", command))?;
+        if cancel.is_cancelled() {
+            return Ok(Outcome {
+                status: ExitStatus::from_raw(SIGKILL),
+                stop: Some(Stop::Cancelled),
+            });
+        }
+        recording.stdout.iter().for_each(|line| on_stdout(line));
+        recording.stderr.iter().for_each(|line| on_stderr(line));
+        for output in &recording.outputs {
+            match output.saved {
+                Some(ref saved) => {
+                    std::fs::copy(saved, &output.path)?;
+                }
+                None => {
+                    File::create(&output.path)?;
+                }
+            }
+        }
+        Ok(Outcome {
+            // wait status layout: exit code in the second byte, or the signal in the first
+            status: ExitStatus::from_raw(recording.code.map_or(SIGKILL, |c| c << 8)),
+            stop: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_and_replay() {
+        let recorder = RecordingRunner::new(Arc::new(SystemRunner));
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c").arg("echo out; echo err >&2; exit 2");
+        let outcome = recorder
+            .run(
+                &mut cmd,
+                &mut |_| {},
+                &mut |_| {},
+                &CancelToken::new(),
+                None,
+            )
+            .unwrap();
+        assert_eq!(outcome.status.code(), Some(2));
+        let recordings = recorder.recordings();
+        assert_eq!(
+            recordings,
+            [Recording {
+                command: "sh -c 'echo out; echo err >&2; exit 2'".into(),
+                stdout: vec!["out".into()],
+                stderr: vec!["err".into()],
+                code: Some(2),
+                outputs: Vec::new(),
+            }]
+        );
+
+        let replay = ReplayRunner::new();
+        replay.push(recordings[0].clone());
+        let (mut stdout, mut stderr) = (Vec::new(), Vec::new());
+        let outcome = replay
+            .run(
+                &mut cmd,
+                &mut |line| stdout.push(line.to_string()),
+                &mut |line| stderr.push(line.to_string()),
+                &CancelToken::new(),
+                None,
+            )
+            .unwrap();
+        assert_eq!(outcome.status.code(), Some(2));
+        assert_eq!(stdout, ["out"]);
+        assert_eq!(stderr, ["err"]);
+        assert!(replay.is_exhausted());
+        assert!(replay
+            .run(
+                &mut cmd,
+                &mut |_| {},
+                &mut |_| {},
+                &CancelToken::new(),
+                None
+            )
+            .is_err());
+
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c").arg("exit 3");
+        let outcome = recorder
+            .run_inherited(&mut cmd, &CancelToken::new(), None)
+            .unwrap();
+        assert_eq!(outcome.status.code(), Some(3));
+        assert_eq!(
+            recorder.recordings()[1],
+            Recording {
+                code: Some(3),
+                ..Recording::success("sh -c 'exit 3'", &[] as &[&str])
+            }
+        );
+    }
+
+    #[test]
+    fn test_replay_saved_outputs() {
+        let dir = std::env::temp_dir().join(format!("runner-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let output = dir.join(".out.partial.txt");
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c").arg("echo written > \"$0\"").arg(&output);
+        let frames = dir.join("frame%02d.txt");
+        let mut sequence = Command::new("sh");
+        sequence
+            .arg("-c")
+            .arg("for i in 1 2; do echo \"frame $i\" > \"$(printf \"$0\" $i)\"; done")
+            .arg(&frames);
+
+        let recorder =
+            RecordingRunner::new(Arc::new(SystemRunner)).keep_outputs_in(dir.join("outputs"));
+        let run = |runner: &dyn CommandRunner, cmd: &mut Command| {
+            runner
+                .run(cmd, &mut |_| {}, &mut |_| {}, &CancelToken::new(), None)
+                .unwrap()
+        };
+        run(&recorder, &mut cmd);
+        run(&recorder, &mut sequence);
+        let recordings = recorder.recordings();
+        assert_eq!(recordings[0].outputs.len(), 1);
+        assert_eq!(recordings[0].outputs[0].path, output);
+        let mut written = recordings[1]
+            .outputs
+            .iter()
+            .map(|output| output.path.clone())
+            .collect::<Vec<_>>();
+        written.sort();
+        assert_eq!(written, [dir.join("frame01.txt"), dir.join("frame02.txt")]);
+        let saved = dir.join("recordings.json");
+        recorder.save(&saved).unwrap();
+
+        for path in [&output, &written[0], &written[1]] {
+            std::fs::remove_file(path).unwrap();
+        }
+        let replay = ReplayRunner::load(&saved).unwrap();
+        assert!(run(&replay, &mut cmd).status.success());
+        assert!(run(&replay, &mut sequence).status.success());
+        assert_eq!(std::fs::read_to_string(&output).unwrap(), "written\n");
+        assert_eq!(std::fs::read_to_string(&written[1]).unwrap(), "frame 2\n");
+        assert!(replay.is_exhausted());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
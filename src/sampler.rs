@@ -0,0 +1,139 @@
+use std::{collections::BTreeMap, time::Duration};
+
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{ffmpeg::VideoTimestamp, iter::HysteresisParams};
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct SamplingParams {
+    /// Spacing of the first pass over the whole video.
+    pub coarse_interval: Duration,
+    /// Spacing between coarse samples whose scores fall on different sides of a threshold.
+    pub fine_interval: Duration,
+}
+
+/// Scores the video every `coarse_interval`, then fills every gap between neighbouring samples
+/// that fall on different sides of the hysteresis thresholds at `fine_interval`, so ranges
+/// start and end at the same samples a fixed `fine_interval` scan would find, as long as no
+/// range is shorter than `coarse_interval`.
+///
+/// Returns the samples in time order.
+pub fn adaptive_sample<T, F>(
+    duration: &VideoTimestamp,
+    sampling: SamplingParams,
+    hysteresis: HysteresisParams,
+    sample: F,
+) -> anyhow::Result<Vec<(T, f64)>>
+where
+    T: Send,
+    F: Fn(&VideoTimestamp) -> anyhow::Result<(T, f64)> + Sync,
+{
+    if sampling.coarse_interval.is_zero() || sampling.fine_interval.is_zero() {
+        return Err(anyhow::anyhow!(
+            "Sampling intervals must be greater than zero"
+        ));
+    }
+    let score_all = |at: Vec<VideoTimestamp>| -> anyhow::Result<Vec<(VideoTimestamp, (T, f64))>> {
+        at.into_par_iter()
+            .map(|t| sample(&t).map(|s| (t, s)))
+            .collect()
+    };
+    let level = |score: f64| (score >= hysteresis.exit) as u8 + (score >= hysteresis.enter) as u8;
+
+    let mut samples = BTreeMap::new();
+    samples.extend(score_all(steps(
+        VideoTimestamp::zero(),
+        *duration,
+        sampling.coarse_interval,
+    ))?);
+
+    let fine = samples
+        .iter()
+        .zip(samples.iter().skip(1))
+        .filter(|((_, (_, a)), (_, (_, b)))| level(*a) != level(*b))
+        .flat_map(|((a, _), (b, _))| steps(*a + sampling.fine_interval, *b, sampling.fine_interval))
+        .collect::<Vec<_>>();
+    samples.extend(score_all(fine)?);
+
+    Ok(samples.into_values().collect())
+}
+
+/// Timestamps from `start` up to but excluding `end`, `interval` apart.
+fn steps(start: VideoTimestamp, end: VideoTimestamp, interval: Duration) -> Vec<VideoTimestamp> {
+    let mut steps = Vec::new();
+    let mut t = start;
+    while t < end {
+        steps.push(t);
+        t = t + interval;
+    }
+    steps
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::iter::iter_hysteresis_range;
+
+    #[test]
+    fn test_adaptive_matches_fixed_scan() {
+        let duration = VideoTimestamp::from_seconds(600);
+        let hysteresis = HysteresisParams {
+            enter: 0.6,
+            exit: 0.4,
+            max_gap: 2.0,
+            min_duration: 4.0,
+        };
+        let score = |t: &VideoTimestamp| {
+            let s = t.as_float_seconds();
+            if (123.0..161.0).contains(&s) || (400.0..408.0).contains(&s) {
+                0.9
+            } else {
+                0.1
+            }
+        };
+        let ranges = |samples: &[(VideoTimestamp, f64)]| {
+            iter_hysteresis_range(
+                samples.iter(),
+                hysteresis,
+                |(t, _)| t.as_float_seconds(),
+                |(_, s)| *s,
+            )
+            .map(|(a, b)| (a.0, b.0))
+            .collect::<Vec<_>>()
+        };
+
+        let fixed = steps(VideoTimestamp::zero(), duration, Duration::from_secs(1))
+            .into_iter()
+            .map(|t| (t, score(&t)))
+            .collect::<Vec<_>>();
+        let adaptive = adaptive_sample(
+            &duration,
+            SamplingParams {
+                coarse_interval: Duration::from_secs(5),
+                fine_interval: Duration::from_secs(1),
+            },
+            hysteresis,
+            |t| Ok((*t, score(t))),
+        )
+        .unwrap()
+        .into_iter()
+        .map(|(t, _)| (t, score(&t)))
+        .collect::<Vec<_>>();
+
+        assert_eq!(ranges(&adaptive), ranges(&fixed));
+        assert_eq!(ranges(&fixed).len(), 2);
+        assert!(adaptive.len() < fixed.len() / 3);
+
+        assert!(adaptive_sample(
+            &duration,
+            SamplingParams {
+                coarse_interval: Duration::ZERO,
+                fine_interval: Duration::from_secs(1),
+            },
+            hysteresis,
+            |t| Ok((*t, score(t))),
+        )
+        .is_err());
+    }
+}